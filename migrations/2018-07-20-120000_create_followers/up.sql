@@ -0,0 +1,11 @@
+CREATE TABLE followers (
+       id INTEGER NOT NULL,
+       actor TEXT UNIQUE NOT NULL,
+       inbox TEXT NOT NULL,
+       shared_inbox TEXT,
+       follow_id TEXT NOT NULL,
+       datetime INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (id)
+);
+
+CREATE INDEX followers_actor_ix ON followers (actor);
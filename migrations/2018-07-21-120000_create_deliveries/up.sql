@@ -0,0 +1,12 @@
+CREATE TABLE deliveries (
+       id INTEGER NOT NULL,
+       inbox TEXT NOT NULL,
+       json TEXT NOT NULL,
+       attempts INTEGER NOT NULL DEFAULT 0,
+       next_attempt INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       last_error TEXT,
+       datetime INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (id)
+);
+
+CREATE INDEX deliveries_next_attempt_ix ON deliveries (next_attempt);
@@ -0,0 +1,12 @@
+CREATE TABLE inbox_failed (
+       rowid INTEGER NOT NULL,
+       id TEXT UNIQUE NOT NULL,
+       json TEXT NOT NULL,
+       error TEXT NOT NULL,
+       attempts INTEGER NOT NULL DEFAULT 1,
+       first_failed INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       last_failed INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (rowid)
+);
+
+CREATE INDEX inbox_failed_id_ix ON inbox_failed (id);
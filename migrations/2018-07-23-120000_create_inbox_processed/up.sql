@@ -0,0 +1,7 @@
+CREATE TABLE inbox_processed (
+       id TEXT NOT NULL,
+       datetime INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (id)
+);
+
+CREATE INDEX inbox_processed_datetime_ix ON inbox_processed (datetime);
@@ -0,0 +1,12 @@
+CREATE TABLE remote_actors (
+       id TEXT NOT NULL,
+       json TEXT NOT NULL,
+       inbox TEXT NOT NULL,
+       shared_inbox TEXT,
+       key_id TEXT,
+       public_key_pem TEXT,
+       fetched INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (id)
+);
+
+CREATE INDEX remote_actors_key_id_ix ON remote_actors (key_id);
@@ -0,0 +1,7 @@
+CREATE TABLE tombstones (
+       uri_name TEXT NOT NULL,
+       title TEXT NOT NULL,
+       published INTEGER NOT NULL,
+       deleted INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (uri_name)
+);
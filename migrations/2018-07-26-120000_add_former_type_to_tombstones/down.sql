@@ -0,0 +1,13 @@
+CREATE TABLE tombstones_old (
+       uri_name TEXT NOT NULL,
+       title TEXT NOT NULL,
+       published INTEGER NOT NULL,
+       deleted INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (uri_name)
+);
+
+INSERT INTO tombstones_old (uri_name, title, published, deleted)
+       SELECT uri_name, title, published, deleted FROM tombstones;
+
+DROP TABLE tombstones;
+ALTER TABLE tombstones_old RENAME TO tombstones;
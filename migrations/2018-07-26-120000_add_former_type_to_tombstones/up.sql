@@ -0,0 +1 @@
+ALTER TABLE tombstones ADD COLUMN former_type TEXT NOT NULL DEFAULT 'Note';
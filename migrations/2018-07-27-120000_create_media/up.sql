@@ -0,0 +1,14 @@
+CREATE TABLE media (
+       hash TEXT NOT NULL,
+       file TEXT NOT NULL UNIQUE,
+       filename TEXT NOT NULL,
+       media_type TEXT NOT NULL,
+       size INTEGER NOT NULL,
+       width INTEGER,
+       height INTEGER,
+       duration REAL,
+       uploaded INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (hash)
+);
+
+CREATE INDEX media_file_ix ON media (file);
@@ -0,0 +1,20 @@
+CREATE TABLE media_old (
+       hash TEXT NOT NULL,
+       file TEXT NOT NULL UNIQUE,
+       filename TEXT NOT NULL,
+       media_type TEXT NOT NULL,
+       size INTEGER NOT NULL,
+       width INTEGER,
+       height INTEGER,
+       duration REAL,
+       uploaded INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       PRIMARY KEY (hash)
+);
+
+INSERT INTO media_old (hash, file, filename, media_type, size, width, height, duration, uploaded)
+       SELECT hash, file, filename, media_type, size, width, height, duration, uploaded FROM media;
+
+DROP TABLE media;
+ALTER TABLE media_old RENAME TO media;
+
+CREATE INDEX media_file_ix ON media (file);
@@ -0,0 +1,11 @@
+ALTER TABLE media ADD COLUMN title TEXT;
+ALTER TABLE media ADD COLUMN artist TEXT;
+ALTER TABLE media ADD COLUMN album TEXT;
+ALTER TABLE media ADD COLUMN track_number INTEGER;
+ALTER TABLE media ADD COLUMN year INTEGER;
+ALTER TABLE media ADD COLUMN genre TEXT;
+ALTER TABLE media ADD COLUMN isrc TEXT;
+ALTER TABLE media ADD COLUMN cover TEXT;
+ALTER TABLE media ADD COLUMN sample_rate INTEGER;
+ALTER TABLE media ADD COLUMN channels INTEGER;
+ALTER TABLE media ADD COLUMN bitrate INTEGER;
@@ -0,0 +1,32 @@
+CREATE TABLE media_old (
+       hash TEXT NOT NULL,
+       file TEXT NOT NULL UNIQUE,
+       filename TEXT NOT NULL,
+       media_type TEXT NOT NULL,
+       size INTEGER NOT NULL,
+       width INTEGER,
+       height INTEGER,
+       duration REAL,
+       uploaded INTEGER NOT NULL DEFAULT (strftime('%s','now')),
+       title TEXT,
+       artist TEXT,
+       album TEXT,
+       track_number INTEGER,
+       year INTEGER,
+       genre TEXT,
+       isrc TEXT,
+       cover TEXT,
+       sample_rate INTEGER,
+       channels INTEGER,
+       bitrate INTEGER,
+       peaks TEXT,
+       PRIMARY KEY (hash)
+);
+
+INSERT INTO media_old (hash, file, filename, media_type, size, width, height, duration, uploaded, title, artist, album, track_number, year, genre, isrc, cover, sample_rate, channels, bitrate, peaks)
+       SELECT hash, file, filename, media_type, size, width, height, duration, uploaded, title, artist, album, track_number, year, genre, isrc, cover, sample_rate, channels, bitrate, peaks FROM media;
+
+DROP TABLE media;
+ALTER TABLE media_old RENAME TO media;
+
+CREATE INDEX media_file_ix ON media (file);
@@ -1,30 +1,34 @@
-use ::std::io::{self, Read};
+use ::std::io::Read;
 use ::std::path::{Path, PathBuf};
 
 use ::chrono::offset::{TimeZone, Utc};
 use ::diesel::prelude::*;
+use ::diesel::result::{DatabaseErrorKind, Error as DieselError};
+use ::diesel::sqlite::SqliteConnection;
 use ::rocket::{
-    Data, Route,
+    Data, Request, Response, Route,
+    http::Status,
     request::State,
-    response::NamedFile
+    response::{self, NamedFile, Responder}
 };
 use ::rocket_contrib::Json;
 use ::serde_json::{self, Value};
 
+use api::admin::Admin;
 use api::error::Error;
+use api::html::{self, Templates};
+use api::negotiate::{Format, Negotiated};
 use config::Config;
 use db::Database;
+use delivery;
+use inbox::{self, InboxWorker};
+use media;
 use models;
+use ns;
+use remote;
 use schema;
 use sig::ValidSignature;
 
-mod ns {
-    pub const ACTIVITYSTREAMS: &str = "https://www.w3.org/ns/activitystreams";
-    pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
-
-    pub const SECURITY: &str = "https://w3id.org/security/v1";
-}
-
 fn get_actor(config: &Config, _database: &Database) -> Result<Value, Error> {
     let actor_url = config.actor_url();
         
@@ -38,11 +42,15 @@ fn get_actor(config: &Config, _database: &Database) -> Result<Value, Error> {
         "id": actor_url,
         "preferredUsername": config.actor_username,
         "name": config.actor_name,
+        "summary": config.actor_bio,
+        "url": actor_url,
 	"inbox": config.inbox_url(),
         "outbox": config.outbox_url(),
+        "followers": config.followers_url(),
+        "following": config.following_url(),
 
 	"publicKey": {
-	    "id": format!("{}#main-key", actor_url),
+	    "id": config.key_id(),
 	    "owner": actor_url,
 	    "publicKeyPem": config.pub_key
 	},
@@ -51,6 +59,16 @@ fn get_actor(config: &Config, _database: &Database) -> Result<Value, Error> {
             "type": "Image",
             "mediaType": "image/jpeg",
             "url": config.media_url("icon.jpg")
+        },
+
+        "generator": {
+            "type": "Application",
+            "implements": [
+                {
+                    "name": "RFC-9421: HTTP Message Signatures",
+                    "href": remote::RFC9421
+                }
+            ]
         }
     }))
 }
@@ -60,181 +78,688 @@ fn format_timestamp(timestamp: i32) -> String {
         .to_rfc3339()
 }
 
-fn get_content(post: &models::Post) -> String {
-    let mut content = String::new();
-    
-    for piece in post.body.pieces.iter() {
-        let text: &str = match piece {
-            models::Piece::Html(html) => html,
-            _ => "FIXME"
+// xsd:duration, which is what ActivityStreams expects
+fn format_duration(seconds: f64) -> String {
+    format!("PT{}S", seconds)
+}
+
+fn get_attachment(config: &Config, kind: &str, media: &models::Media) -> Value {
+    let mut attachment = json!({
+        "type": kind,
+        "mediaType": media.media_type,
+        "url": config.media_url(&media.file)
+    });
+
+    // Mastodon shows an attachment's name as its description
+    if let Some(ref alt) = media.alt {
+        attachment["name"] = json!(alt);
+    }
+
+    if let Some(duration) = media.duration {
+        attachment["duration"] = json!(format_duration(duration));
+    }
+
+    if let (Some(width), Some(height)) = (media.width, media.height) {
+        attachment["width"] = json!(width);
+        attachment["height"] = json!(height);
+    }
+
+    attachment
+}
+
+fn get_link(media_type: &str, href: String) -> Value {
+    json!({
+        "type": "Link",
+        "mediaType": media_type,
+        "href": href
+    })
+}
+
+// The first media piece decides what a post is, which is what Funkwhale, PeerTube
+// and Pixelfed look at. Posts without media stay Notes
+fn get_primary(post: &models::Post) -> Option<(&'static str, &models::Media)> {
+    post.body.pieces.iter()
+        .filter_map(|piece| match piece {
+            models::Piece::Html(_) => None,
+            models::Piece::Audio(media) => Some(("Audio", media)),
+            models::Piece::Video(media) => Some(("Video", media)),
+            models::Piece::Image(media) => Some(("Image", media))
+        })
+        .next()
+}
+
+fn get_object_type(post: &models::Post) -> &'static str {
+    get_primary(post)
+        .map(|(kind, _)| kind)
+        .unwrap_or("Note")
+}
+
+fn get_post_object(post: &models::Post, config: &Config) -> Value {
+    let post_url = config.post_url(&post.uri_name);
+
+    let attachment = post.body.pieces.iter()
+        .filter_map(|piece| match piece {
+            models::Piece::Html(_) => None,
+            models::Piece::Audio(media) => Some(get_attachment(config, "Audio", media)),
+            models::Piece::Video(media) => Some(get_attachment(config, "Video", media)),
+            models::Piece::Image(media) => Some(get_attachment(config, "Image", media))
+        })
+        .collect::<Vec<_>>();
+
+    // content and attachment are what Mastodon renders, whatever the type
+    let mut object = json!({
+        "type": get_object_type(post),
+        "id": post_url,
+        "attributedTo": config.actor_url(),
+        "published": format_timestamp(post.datetime),
+        "to": [
+            ns::PUBLIC
+        ],
+        "name": post.title,
+        "content": html::content(post),
+        "attachment": attachment,
+        "url": post_url
+    });
+
+    if let Some((kind, media)) = get_primary(post) {
+        // Uploads aren't transcoded, so the file as uploaded is the only rendition there
+        // is to link. Other pieces of the post are separate media, not variants of this one.
+        object["url"] = json!([
+            get_link(&media.media_type, config.media_url(&media.file)),
+            get_link("text/html", post_url)
+        ]);
+
+        if let Some(duration) = media.duration {
+            object["duration"] = json!(format_duration(duration));
+        }
+
+        if let (Some(width), Some(height)) = (media.width, media.height) {
+            object["width"] = json!(width);
+            object["height"] = json!(height);
+        }
+
+        // cover art for a track or a poster for a video
+        let cover = post.body.pieces.iter()
+            .filter_map(|piece| match piece {
+                models::Piece::Image(media) => Some(media),
+                _ => None
+            })
+            .next();
+
+        match cover {
+            Some(cover) if kind != "Image" => {
+                object["icon"] = json!({
+                    "type": "Image",
+                    "mediaType": cover.media_type,
+                    "url": config.media_url(&cover.file)
+                });
+            },
+            _ => {}
         };
-        
-        content.push_str(&text);
+
+        // a track's waveform gives timelines something to show, standing in for missing cover art
+        if kind == "Audio" {
+            let waveform = json!({
+                "type": "Image",
+                "mediaType": "image/png",
+                "url": config.waveform_url(media::file_hash(&media.file))
+            });
+
+            if object.get("icon").is_none() {
+                object["icon"] = waveform.clone();
+            }
+
+            object["image"] = waveform;
+        }
     }
 
-    content
+    object
 }
 
-fn get_create_note(post: &models::Post, config: &Config) -> Value {
-    let actor_url = config.actor_url();
-    let published = format_timestamp(post.datetime);
-    
+fn get_create(post: &models::Post, config: &Config) -> Value {
     json!({
         "type": "Create",
         "id": config.activity_url(&post.uri_name),
-        "actor": actor_url,
-        "published": published,
+        "actor": config.actor_url(),
+        "published": format_timestamp(post.datetime),
         "to": [
             ns::PUBLIC
         ],
-        "object": {
-            "type": "Note",
-            "id": config.post_url(&post.uri_name),
-            "attributedTo": actor_url,
-            "published": published,
-            "name": post.title,
-            "content": get_content(post)
+        "object": get_post_object(post, config)
+    })
+}
+
+fn get_tombstone(id: String, former_type: &str, tombstone: &models::Tombstone) -> Value {
+    json!({
+        "type": "Tombstone",
+        "id": id,
+        "formerType": former_type,
+        "name": tombstone.title,
+        "published": format_timestamp(tombstone.published),
+        "deleted": format_timestamp(tombstone.deleted)
+    })
+}
+
+fn with_context(mut object: Value) -> Value {
+    object["@context"] = json!(ns::ACTIVITYSTREAMS);
+    object
+}
+
+fn now() -> i32 {
+    Utc::now().timestamp() as i32
+}
+
+pub fn create_post(config: &Config, conn: &SqliteConnection, new_post: &models::NewPost) -> Result<(), ::failure::Error> {
+    conn.transaction::<_, ::failure::Error, _>(|| {
+        ::diesel::insert_into(schema::posts::table)
+            .values(new_post)
+            .execute(conn)?;
+
+        // a uri_name can be reused once its old post is gone
+        ::diesel::delete(schema::tombstones::table.filter(schema::tombstones::uri_name.eq(&new_post.uri_name)))
+            .execute(conn)?;
+
+        let post = schema::posts::table
+            .filter(schema::posts::uri_name.eq(&new_post.uri_name))
+            .first::<models::Post>(conn)?;
+
+        delivery::enqueue_to_followers(conn, &with_context(get_create(&post, config)))?;
+
+        Ok(())
+    })
+}
+
+pub fn update_post(config: &Config, conn: &SqliteConnection, uri_name: &str, title: &str, body: &models::Body) -> Result<(), ::failure::Error> {
+    conn.transaction::<_, ::failure::Error, _>(|| {
+        let updated = ::diesel::update(schema::posts::table.filter(schema::posts::uri_name.eq(uri_name)))
+            .set((
+                schema::posts::title.eq(title),
+                schema::posts::body.eq(body)
+            ))
+            .execute(conn)?;
+
+        if updated == 0 {
+            return Err(format_err!("No post named {}", uri_name));
+        }
+
+        let post = schema::posts::table
+            .filter(schema::posts::uri_name.eq(uri_name))
+            .first::<models::Post>(conn)?;
+
+        let timestamp = now();
+
+        let mut object = get_post_object(&post, config);
+        object["updated"] = json!(format_timestamp(timestamp));
+
+        let update = json!({
+            "@context": ns::ACTIVITYSTREAMS,
+
+            "type": "Update",
+            "id": format!("{}#updates/{}", config.activity_url(uri_name), timestamp),
+            "actor": config.actor_url(),
+            "to": [
+                ns::PUBLIC
+            ],
+            "object": object
+        });
+
+        delivery::enqueue_to_followers(conn, &update)?;
+
+        Ok(())
+    })
+}
+
+// replaces the post with a tombstone and tells followers it's gone
+pub fn delete_post(config: &Config, conn: &SqliteConnection, uri_name: &str) -> Result<(), ::failure::Error> {
+    conn.transaction::<_, ::failure::Error, _>(|| {
+        let post = schema::posts::table
+            .filter(schema::posts::uri_name.eq(uri_name))
+            .first::<models::Post>(conn)
+            .optional()?
+            .ok_or(format_err!("No post named {}", uri_name))?;
+
+        let new_tombstone = models::NewTombstone {
+            uri_name: post.uri_name.clone(),
+            title: post.title.clone(),
+            published: post.datetime,
+            former_type: get_object_type(&post).to_owned()
+        };
+
+        ::diesel::replace_into(schema::tombstones::table)
+            .values(&new_tombstone)
+            .execute(conn)?;
+
+        ::diesel::delete(schema::posts::table.filter(schema::posts::id.eq(post.id)))
+            .execute(conn)?;
+
+        let tombstone = schema::tombstones::table
+            .filter(schema::tombstones::uri_name.eq(uri_name))
+            .first::<models::Tombstone>(conn)?;
+
+        let delete = json!({
+            "@context": ns::ACTIVITYSTREAMS,
+
+            "type": "Delete",
+            "id": format!("{}#delete", config.activity_url(uri_name)),
+            "actor": config.actor_url(),
+            "to": [
+                ns::PUBLIC
+            ],
+            "object": get_tombstone(config.post_url(uri_name), &tombstone.former_type, &tombstone)
+        });
+
+        delivery::enqueue_to_followers(conn, &delete)?;
+
+        Ok(())
+    })
+}
+
+#[derive(Debug, FromForm)]
+struct PostsQuery {
+    pub page: Option<bool>,
+    pub max_id: Option<i32>,
+    pub min_id: Option<i32>
+}
+
+struct PostsPage {
+    posts: Vec<models::Post>,
+    // cursors for the neighbouring pages, if there are any posts on them
+    newer: Option<i32>,
+    older: Option<i32>
+}
+
+fn get_posts_page(config: &Config, database: &Database, query: &PostsQuery) -> Result<PostsPage, Error> {
+    let page_size = config.outbox_page_size;
+
+    // min_id pages walk towards newer posts, so they're loaded oldest first and reversed
+    let posts = match (query.max_id, query.min_id) {
+        (_, Some(min_id)) => {
+            let mut posts = schema::posts::table
+                .filter(schema::posts::id.gt(min_id))
+                .filter(schema::posts::id.lt(query.max_id.unwrap_or(i32::max_value())))
+                .order(schema::posts::id.asc())
+                .limit(page_size)
+                .load::<models::Post>(&database.conn)?;
+
+            posts.reverse();
+            posts
+        },
+        (Some(max_id), None) => {
+            schema::posts::table
+                .filter(schema::posts::id.lt(max_id))
+                .order(schema::posts::id.desc())
+                .limit(page_size)
+                .load::<models::Post>(&database.conn)?
+        },
+        (None, None) => {
+            schema::posts::table
+                .order(schema::posts::id.desc())
+                .limit(page_size)
+                .load::<models::Post>(&database.conn)?
         }
+    };
+
+    let mut newer = None;
+    let mut older = None;
+
+    if let Some(newest_id) = posts.first().map(|post| post.id) {
+        let newer_post = schema::posts::table
+            .select(schema::posts::id)
+            .filter(schema::posts::id.gt(newest_id))
+            .first::<i32>(&database.conn)
+            .optional()?;
+
+        newer = newer_post.map(|_| newest_id);
+    }
+
+    if let Some(oldest_id) = posts.last().map(|post| post.id) {
+        let older_post = schema::posts::table
+            .select(schema::posts::id)
+            .filter(schema::posts::id.lt(oldest_id))
+            .first::<i32>(&database.conn)
+            .optional()?;
+
+        older = older_post.map(|_| oldest_id);
+    }
+
+    Ok(PostsPage {
+        posts: posts,
+        newer: newer,
+        older: older
     })
 }
 
 fn get_outbox(config: &Config, database: &Database) -> Result<Value, Error> {
-    let posts = schema::posts::table
-        .order(schema::posts::id.desc())
-        .load::<models::Post>(&database.conn)?;
-    
-    let items = posts.into_iter()
-        .map(|post| get_create_note(&post, config))
-        .collect::<Vec<_>>();
-    
+    let total_items = schema::posts::table
+        .count()
+        .get_result::<i64>(&database.conn)?;
+
+    let outbox_url = config.outbox_url();
+
     Ok(json!({
         "@context": ns::ACTIVITYSTREAMS,
 
         "type": "OrderedCollection",
-        "id": config.outbox_url(),
-        "totalItems": items.len(),
-        "items": items
+        "id": outbox_url,
+        "totalItems": total_items,
+        "first": format!("{}?page=true", outbox_url),
+        "last": format!("{}?min_id=0&page=true", outbox_url)
     }))
 }
 
-#[get("/")]
-fn actor(config: State<Config>, database: Database) -> Result<Json<Value>, Error> {
-    Ok(Json(get_actor(&config, &database)?))
+fn get_outbox_page(config: &Config, database: &Database, query: &PostsQuery) -> Result<Value, Error> {
+    let posts_page = get_posts_page(config, database, query)?;
+
+    let outbox_url = config.outbox_url();
+
+    let mut page_url = format!("{}?page=true", outbox_url);
+
+    if let Some(max_id) = query.max_id {
+        page_url.push_str(&format!("&max_id={}", max_id));
+    }
+
+    if let Some(min_id) = query.min_id {
+        page_url.push_str(&format!("&min_id={}", min_id));
+    }
+
+    let items = posts_page.posts.iter()
+        .map(|post| get_create(post, config))
+        .collect::<Vec<_>>();
+
+    let mut page = json!({
+        "@context": ns::ACTIVITYSTREAMS,
+
+        "type": "OrderedCollectionPage",
+        "id": page_url,
+        "partOf": outbox_url,
+        "orderedItems": items
+    });
+
+    if let Some(newest_id) = posts_page.newer {
+        page["prev"] = json!(format!("{}?page=true&min_id={}", outbox_url, newest_id));
+    }
+
+    if let Some(oldest_id) = posts_page.older {
+        page["next"] = json!(format!("{}?page=true&max_id={}", outbox_url, oldest_id));
+    }
+
+    Ok(page)
+}
+
+const COLLECTION_PAGE_SIZE: i64 = 20;
+
+#[derive(Debug, FromForm)]
+struct PageQuery {
+    pub page: i64
 }
 
-#[post("/_inbox", data = "<data>")]
-fn inbox(data: Data, config: State<Config>, database: Database, signature: Result<ValidSignature, Error>) -> Result<Json<Value>, Error> {
-    let _ = signature?;
+fn page_url(collection_url: &str, page: i64) -> String {
+    format!("{}?page={}", collection_url, page)
+}
 
-    let mut data_str = String::new();
-    data.open().read_to_string(&mut data_str)?;
-    
-    //println!("data_str:\n\n{}\n", data_str);
+fn get_collection(collection_url: &str, total_items: i64, show_items: bool) -> Value {
+    let mut collection = json!({
+        "@context": ns::ACTIVITYSTREAMS,
 
-    let activity_json: Value = serde_json::from_str(&data_str)
-        .map_err(Error::bad_request)?;
+        "type": "OrderedCollection",
+        "id": collection_url,
+        "totalItems": total_items
+    });
 
-    //println!("activity_json: {:?}", activity_json);
-    
-    let new_activity = models::NewActivity::from_json(activity_json)
-        .map_err(Error::bad_request)?;
+    if show_items {
+        collection["first"] = json!(page_url(collection_url, 1));
+    }
 
-    //println!("new_activity: {:?}", new_activity);
+    collection
+}
 
-    ::diesel::insert_into(schema::inbox::table)
-        .values(&new_activity)
-        .execute(&database.conn)?;
+fn get_collection_page(collection_url: &str, page: i64, total_items: i64, items: Vec<String>) -> Value {
+    let mut collection_page = json!({
+        "@context": ns::ACTIVITYSTREAMS,
 
-    // TODO: this goes in a worker thread
+        "type": "OrderedCollectionPage",
+        "id": page_url(collection_url, page),
+        "partOf": collection_url,
+        "totalItems": total_items,
+        "orderedItems": items
+    });
 
-    let activities = schema::inbox::table
-        .order(schema::inbox::rowid.asc())
-        .load::<models::Activity>(&database.conn)?;
+    if page > 1 {
+        collection_page["prev"] = json!(page_url(collection_url, page - 1));
+    }
 
-    for activity in activities {
-        match handle_activity(&config, &activity) {
-            Ok(()) => {
-                println!("handle_activity() succeeded");
-            },
-            Err(e) => {
-                println!("handle_activity() failed: {:?}", e);
-            }
-        };
+    if page * COLLECTION_PAGE_SIZE < total_items {
+        collection_page["next"] = json!(page_url(collection_url, page + 1));
+    }
 
-        let delete_q = schema::inbox::table.filter(
-            schema::inbox::rowid.eq(activity.rowid)
-        );
+    collection_page
+}
 
-        ::diesel::delete(delete_q).execute(&database.conn)
-            .map(|_| {
-                println!("delete(rowid = {}) succeeded", activity.rowid);
-            })
-            .map_err(|e| {
-                println!("delete(rowid = {}) failed: {:?}", activity.rowid, e);
-                e
-            })?;        
+fn get_followers_page(config: &Config, database: &Database, page: i64) -> Result<Value, Error> {
+    if config.hide_followers || page < 1 {
+        return Err(Error::NotFound);
     }
-    
-    Ok(Json(Value::Null))
+
+    let total_items = schema::followers::table
+        .count()
+        .get_result::<i64>(&database.conn)?;
+
+    let followers = schema::followers::table
+        .order(schema::followers::id.desc())
+        .offset((page - 1) * COLLECTION_PAGE_SIZE)
+        .limit(COLLECTION_PAGE_SIZE)
+        .load::<models::Follower>(&database.conn)?;
+
+    let items = followers.into_iter()
+        .map(|follower| follower.actor)
+        .collect();
+
+    Ok(get_collection_page(&config.followers_url(), page, total_items, items))
+}
+
+fn get_profile(format: Format, templates: &Templates, config: &Config, database: &Database, query: &PostsQuery) -> Result<Negotiated, Error> {
+    match format {
+        Format::ActivityPub => {
+            Ok(Negotiated::ActivityPub(Status::Ok, get_actor(config, database)?))
+        },
+        Format::Html => {
+            let posts_page = get_posts_page(config, database, query)?;
+            let actor_url = config.actor_url();
+
+            let newer_url = posts_page.newer
+                .map(|newest_id| format!("{}?min_id={}", actor_url, newest_id));
+
+            let older_url = posts_page.older
+                .map(|oldest_id| format!("{}?max_id={}", actor_url, oldest_id));
+
+            Ok(Negotiated::Html(Status::Ok, html::actor(templates, config, &posts_page.posts, newer_url, older_url)?))
+        }
+    }
+}
+
+#[get("/", rank = 2)]
+fn actor(format: Format, templates: State<Templates>, config: State<Config>, database: Database) -> Result<Negotiated, Error> {
+    let query = PostsQuery {
+        page: None,
+        max_id: None,
+        min_id: None
+    };
+
+    get_profile(format, &templates, &config, &database, &query)
+}
+
+#[get("/?<query>", rank = 1)]
+fn actor_page(query: PostsQuery, format: Format, templates: State<Templates>, config: State<Config>, database: Database) -> Result<Negotiated, Error> {
+    get_profile(format, &templates, &config, &database, &query)
 }
 
-fn handle_activity(config: &Config, activity: &models::Activity) -> Result<(), ::failure::Error> {
-    let json: Value = serde_json::from_str(&activity.json)?;
+fn get_object<F, G>(templates: &Templates, config: &Config, database: &Database, format: Format, uri_name: &str, found: F, gone: G) -> Result<Negotiated, Error>
+    where F: Fn(&models::Post) -> Value,
+          G: Fn(&models::Tombstone) -> Value
+{
+    let post = schema::posts::table
+        .filter(schema::posts::uri_name.eq(uri_name))
+        .first::<models::Post>(&database.conn)
+        .optional()?;
+
+    if let Some(post) = post {
+        return Ok(match format {
+            Format::ActivityPub => Negotiated::ActivityPub(Status::Ok, with_context(found(&post))),
+            Format::Html => Negotiated::Html(Status::Ok, html::post(templates, config, &post)?)
+        });
+    }
+
+    let tombstone = schema::tombstones::table
+        .filter(schema::tombstones::uri_name.eq(uri_name))
+        .first::<models::Tombstone>(&database.conn)
+        .optional()?
+        .ok_or(Error::NotFound)?;
+
+    Ok(match format {
+        Format::ActivityPub => Negotiated::ActivityPub(Status::Gone, with_context(gone(&tombstone))),
+        Format::Html => Negotiated::Html(Status::Gone, html::tombstone(templates, config, &tombstone)?)
+    })
+}
+
+// ranked below every other single-segment route, since any of them could be a uri_name
+#[get("/<uri_name>", rank = 3)]
+fn post(uri_name: String, format: Format, templates: State<Templates>, config: State<Config>, database: Database) -> Result<Negotiated, Error> {
+    get_object(
+        &templates, &config, &database, format, &uri_name,
+        |post| get_post_object(post, &config),
+        |tombstone| get_tombstone(config.post_url(&uri_name), &tombstone.former_type, tombstone)
+    )
+}
+
+#[get("/_activity/<uri_name>")]
+fn activity(uri_name: String, format: Format, templates: State<Templates>, config: State<Config>, database: Database) -> Result<Negotiated, Error> {
+    get_object(
+        &templates, &config, &database, format, &uri_name,
+        |post| get_create(post, &config),
+        |tombstone| get_tombstone(config.activity_url(&uri_name), "Create", tombstone)
+    )
+}
+
+#[derive(Debug)]
+enum InboxResponse {
+    Accepted,
+    Duplicate
+}
+
+impl<'r> Responder<'r> for InboxResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let outcome = match self {
+            InboxResponse::Accepted => "accepted",
+            InboxResponse::Duplicate => "duplicate"
+        };
+
+        Response::build_from(Json(json!({ "status": outcome })).respond_to(request)?)
+            .status(Status::Accepted)
+            .ok()
+    }
+}
+
+#[post("/_inbox", data = "<data>")]
+fn inbox(data: Data, worker: State<InboxWorker>, database: Database, signature: Result<ValidSignature, Error>) -> Result<InboxResponse, Error> {
+    let signature = signature?;
+
+    let mut data_bytes = Vec::new();
+    data.open().read_to_end(&mut data_bytes)?;
+
+    signature.verify_body(&data_bytes)
+        .map_err(Error::bad_request)?;
+
+    let data_str = String::from_utf8(data_bytes)
+        .map_err(Error::bad_request)?;
+
+    let activity_json: Value = serde_json::from_str(&data_str)
+        .map_err(Error::bad_request)?;
+
+    let actor_id = inbox::actor_id(&activity_json)
+        .map_err(Error::bad_request)?
+        .to_owned();
+
+    if actor_id != signature.owner {
+        return Err(Error::forbidden(format!(
+            "Activity actor {} does not match signing key owner {}",
+            actor_id, signature.owner
+        )));
+    }
     
-    let typ: String = json.get("type")
-        .ok_or(format_err!("No 'type' field found"))?
-        .as_str()
-        .ok_or(format_err!("Invalid non-string 'type' field"))?
-        .to_lowercase();
-
-    let actor: &Value = json.get("actor")
-        .ok_or(format_err!("No 'actor' field found"))?;
-
-    let object = json.get("object")
-        .ok_or(format_err!("No 'object' field found"))?;
-
-    match &typ as &str {
-        "follow" => {
-            println!("follow!");
-
-            let object_str = object.as_str()
-                .ok_or(format_err!("Invalid non-string 'object' field"))?;
-            
-            if object_str != config.actor_url() {
-                return Err(format_err!("Object is a different actor"));
-            }
+    let new_activity = models::NewActivity::from_json(activity_json)
+        .map_err(Error::bad_request)?;
+
+    if inbox::is_duplicate(&database.conn, &new_activity.id)? {
+        return Ok(InboxResponse::Duplicate);
+    }
 
-            // TODO: Store in followers collection
+    let inserted = ::diesel::insert_into(schema::inbox::table)
+        .values(&new_activity)
+        .execute(&database.conn);
+
+    match inserted {
+        Ok(_) => {},
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return Ok(InboxResponse::Duplicate);
         },
-        _ => {
-            return Err(format_err!("Unsupported activity type: {}", typ));
+        Err(e) => {
+            return Err(Error::internal(e));
         }
     };
 
-    Ok(())
-}
+    worker.notify();
     
-#[get("/_outbox")]
+    Ok(InboxResponse::Accepted)
+}
+
+#[get("/_outbox", rank = 2)]
 fn outbox(config: State<Config>, database: Database) -> Result<Json<Value>, Error> {
     Ok(Json(get_outbox(&config, &database)?))
 }
 
+#[get("/_outbox?<query>", rank = 1)]
+fn outbox_page(query: PostsQuery, config: State<Config>, database: Database) -> Result<Json<Value>, Error> {
+    Ok(Json(get_outbox_page(&config, &database, &query)?))
+}
 
-#[get("/_media/<file..>")]
-fn media(file: PathBuf, config: State<Config>) -> Result<NamedFile, Error> {
-    let f = NamedFile::open(Path::new(&config.media_dir).join(file))
-        .map_err(|e| match e.kind() {
-            io::ErrorKind::NotFound =>
-                Error::NotFound,
-            _ =>
-                Error::internal(e)
-        })?;
+#[get("/_followers", rank = 2)]
+fn followers(config: State<Config>, database: Database) -> Result<Json<Value>, Error> {
+    let total_items = schema::followers::table
+        .count()
+        .get_result::<i64>(&database.conn)?;
 
-    Ok(f)
+    Ok(Json(get_collection(&config.followers_url(), total_items, !config.hide_followers)))
 }
 
+#[get("/_followers?<query>", rank = 1)]
+fn followers_page(query: PageQuery, config: State<Config>, database: Database) -> Result<Json<Value>, Error> {
+    Ok(Json(get_followers_page(&config, &database, query.page)?))
+}
+
+// jadwiga doesn't follow anyone, but some software expects the collection to exist
+#[get("/_following", rank = 2)]
+fn following(config: State<Config>) -> Json<Value> {
+    Json(get_collection(&config.following_url(), 0, true))
+}
+
+#[get("/_following?<query>", rank = 1)]
+fn following_page(query: PageQuery, config: State<Config>) -> Result<Json<Value>, Error> {
+    if query.page < 1 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(Json(get_collection_page(&config.following_url(), query.page, 0, vec![])))
+}
+
+#[get("/_static/<file..>")]
+fn static_file(file: PathBuf, config: State<Config>) -> Result<NamedFile, Error> {
+    NamedFile::open(Path::new(&config.template_dir).join("static").join(file))
+        .map_err(Error::from_io)
+}
+
+// lists remote inboxes and delivery errors, so it's for the admin only
 #[get("/_status")]
-fn status(database: Database) -> Result<Json<Value>, Error> {
+fn status(_admin: Admin, worker: State<InboxWorker>, database: Database) -> Result<Json<Value>, Error> {
     let activities: Vec<Value> = schema::inbox::table
         .order(schema::inbox::rowid.desc())
         .load::<models::Activity>(&database.conn)?
@@ -246,11 +771,44 @@ fn status(database: Database) -> Result<Json<Value>, Error> {
         })))
         .collect::<Result<Vec<_>, Error>>()?;
 
+    let failed: Vec<Value> = schema::inbox_failed::table
+        .order(schema::inbox_failed::last_failed.desc())
+        .load::<models::FailedActivity>(&database.conn)?
+        .iter()
+        .map(|f| json!({
+            "rowid": f.rowid,
+            "id": f.id,
+            "error": f.error,
+            "attempts": f.attempts,
+            "first_failed": format_timestamp(f.first_failed),
+            "last_failed": format_timestamp(f.last_failed)
+        }))
+        .collect();
+
+    let deliveries: Vec<Value> = schema::deliveries::table
+        .order(schema::deliveries::next_attempt.asc())
+        .load::<models::Delivery>(&database.conn)?
+        .iter()
+        .map(|d| json!({
+            "id": d.id,
+            "inbox": d.inbox,
+            "attempts": d.attempts,
+            "next_attempt": format_timestamp(d.next_attempt),
+            "last_error": d.last_error
+        }))
+        .collect();
+
     Ok(Json(json!({
-        "inbox": activities
+        "inbox": activities,
+        "inbox_failed": failed,
+        "inbox_worker": {
+            "paused": worker.is_paused(),
+            "outcomes": worker.outcomes()
+        },
+        "deliveries": deliveries
     })))
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![actor, inbox, outbox, media, status]
+    routes![actor, actor_page, inbox, outbox, outbox_page, followers, followers_page, following, following_page, post, activity, static_file, status]
 }
@@ -0,0 +1,600 @@
+use ::std::collections::HashMap;
+use ::std::path::Path;
+
+use ::chrono::offset::Utc;
+use ::diesel::prelude::*;
+use ::diesel::result::{DatabaseErrorKind, Error as DieselError};
+use ::multipart::server::Multipart;
+use ::rocket::{
+    Catcher, Data, Outcome, Request, Route,
+    http::{ContentType, Cookie, Cookies, Status},
+    request::{self, Form, FormItems, FromForm, FromRequest, State},
+    response::{self, content, Redirect, Responder}
+};
+use ::rocket_contrib::Json;
+use ::serde_json::Value;
+
+use api::activitypub;
+use api::error::Error;
+use api::html::{self, Templates};
+use config::Config;
+use db::Database;
+use inbox::InboxWorker;
+use media;
+use models;
+use password;
+use schema;
+use store::MediaStore;
+
+// The admin area is guarded by a private (encrypted) session cookie holding its expiry
+// time. Rocket marks private cookies HttpOnly and SameSite=Strict, which also keeps
+// other sites from submitting the forms below on our behalf.
+
+const SESSION_COOKIE: &str = "jadwiga_admin";
+const SESSION_LENGTH: i64 = 7 * 24 * 60 * 60;
+
+pub struct Admin;
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let expires = request.cookies()
+            .get_private(SESSION_COOKIE)
+            .and_then(|cookie| cookie.value().parse::<i64>().ok());
+
+        match expires {
+            Some(expires) if expires > Utc::now().timestamp() => Outcome::Success(Admin),
+            _ => Outcome::Failure((Status::Unauthorized, ()))
+        }
+    }
+}
+
+#[derive(Debug)]
+enum AdminResponse {
+    Page(String),
+    Redirect(Redirect)
+}
+
+impl<'r> Responder<'r> for AdminResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            AdminResponse::Page(html) => content::Html(html).respond_to(request),
+            AdminResponse::Redirect(redirect) => redirect.respond_to(request)
+        }
+    }
+}
+
+fn to_index() -> AdminResponse {
+    AdminResponse::Redirect(Redirect::to("/_admin"))
+}
+
+#[derive(Debug, FromForm)]
+struct LoginForm {
+    pub password: String
+}
+
+#[get("/_admin/login")]
+fn login_page(templates: State<Templates>, config: State<Config>) -> Result<AdminResponse, Error> {
+    Ok(AdminResponse::Page(html::admin_login(&templates, &config, None)?))
+}
+
+#[post("/_admin/login", data = "<form>")]
+fn login(form: Form<LoginForm>, mut cookies: Cookies, templates: State<Templates>, config: State<Config>) -> Result<AdminResponse, Error> {
+    let hash = match config.admin_password_hash {
+        Some(ref hash) => hash,
+        None => {
+            let error = "No admin password is set, see `cargo run -- hash-password`";
+            return Ok(AdminResponse::Page(html::admin_login(&templates, &config, Some(error))?));
+        }
+    };
+
+    if !password::verify(&form.get().password, hash).map_err(Error::internal)? {
+        return Ok(AdminResponse::Page(html::admin_login(&templates, &config, Some("Wrong password"))?));
+    }
+
+    let expires = Utc::now().timestamp() + SESSION_LENGTH;
+    cookies.add_private(Cookie::new(SESSION_COOKIE, expires.to_string()));
+
+    Ok(to_index())
+}
+
+#[post("/_admin/logout")]
+fn logout(mut cookies: Cookies) -> AdminResponse {
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+
+    AdminResponse::Redirect(Redirect::to("/_admin/login"))
+}
+
+fn render_index(templates: &Templates, config: &Config, worker: &InboxWorker, database: &Database, notice: Option<String>) -> Result<AdminResponse, Error> {
+    let posts = schema::posts::table
+        .order(schema::posts::id.desc())
+        .load::<models::Post>(&database.conn)?;
+
+    let media_files = schema::media::table
+        .order(schema::media::uploaded.desc())
+        .load::<models::MediaFile>(&database.conn)?;
+
+    Ok(AdminResponse::Page(html::admin_index(templates, config, &posts, &media_files, worker.is_paused(), notice)?))
+}
+
+#[get("/_admin")]
+fn index(_admin: Admin, worker: State<InboxWorker>, templates: State<Templates>, config: State<Config>, database: Database) -> Result<AdminResponse, Error> {
+    render_index(&templates, &config, &worker, &database, None)
+}
+
+// Each piece in the form starts with a `kind` field (html, audio, video or image)
+// followed by that piece's own fields, which the derived FromForm can't express
+#[derive(Debug)]
+struct PieceFields {
+    kind: String,
+    fields: HashMap<String, String>
+}
+
+impl PieceFields {
+    fn get(&self, name: &str) -> Option<String> {
+        self.fields.get(name)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_owned())
+    }
+
+    // blank pieces are dropped, which is also how a piece is removed
+    fn to_piece(&self) -> Option<models::Piece> {
+        if self.kind == "html" {
+            return self.get("html").map(models::Piece::Html);
+        }
+
+        let file = self.get("file")?;
+
+        let media = models::Media {
+            media_type: self.get("media_type")
+                .unwrap_or(media::guess_media_type(&file).to_owned()),
+            duration: self.get("duration").and_then(|duration| duration.parse().ok()),
+            width: self.get("width").and_then(|width| width.parse().ok()),
+            height: self.get("height").and_then(|height| height.parse().ok()),
+            alt: self.get("alt"),
+            caption: self.get("caption"),
+            file: file
+        };
+
+        match self.kind.as_str() {
+            "audio" => Some(models::Piece::Audio(media)),
+            "video" => Some(models::Piece::Video(media)),
+            "image" => Some(models::Piece::Image(media)),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PostForm {
+    uri_name: String,
+    title: String,
+    pieces: Vec<PieceFields>,
+    save: bool
+}
+
+impl<'f> FromForm<'f> for PostForm {
+    type Error = Error;
+
+    fn from_form(items: &mut FormItems<'f>, strict: bool) -> Result<Self, Self::Error> {
+        let mut uri_name = None;
+        let mut title = None;
+        let mut pieces: Vec<PieceFields> = Vec::new();
+        let mut save = false;
+
+        for (key, value) in items {
+            let value = value.url_decode()
+                .map_err(Error::bad_request)?;
+
+            match key.as_str() {
+                "uri_name" => uri_name = Some(value),
+                "title" => title = Some(value),
+                "action" => save = value == "save",
+                "kind" => pieces.push(PieceFields {
+                    kind: value,
+                    fields: HashMap::new()
+                }),
+                "html" | "file" | "media_type" | "duration" | "width" | "height" | "alt" | "caption" => {
+                    let piece = pieces.last_mut()
+                        .ok_or(Error::BadRequest(format!("Field {} comes before any piece kind", key)))?;
+
+                    piece.fields.insert(key.as_str().to_owned(), value);
+                },
+                _ if strict => return Err(Error::BadRequest(format!("Unexpected field {}", key))),
+                _ => {}
+            }
+        }
+
+        Ok(PostForm {
+            uri_name: uri_name.unwrap_or_default(),
+            title: title.ok_or(Error::BadRequest("No title given".to_owned()))?,
+            pieces: pieces,
+            save: save
+        })
+    }
+}
+
+impl PostForm {
+    fn body(&self) -> models::Body {
+        models::Body {
+            pieces: self.pieces.iter()
+                .filter_map(PieceFields::to_piece)
+                .collect()
+        }
+    }
+
+    fn to_post(&self, datetime: i32) -> models::Post {
+        models::Post {
+            id: 0,
+            uri_name: self.uri_name.clone(),
+            datetime: datetime,
+            title: self.title.clone(),
+            body: self.body()
+        }
+    }
+}
+
+// media pieces must point at uploaded files, and take whatever they leave blank from them
+fn check_media(database: &Database, body: &mut models::Body) -> Result<(), String> {
+    for piece in body.pieces.iter_mut() {
+        let media = match piece {
+            models::Piece::Html(_) => continue,
+            models::Piece::Audio(media) | models::Piece::Video(media) | models::Piece::Image(media) => media
+        };
+
+        let media_file = media::get_by_file(&database.conn, &media.file)
+            .map_err(|e| format!("{:?}", e))?
+            .ok_or(format!("There's no uploaded file named {}", media.file))?;
+
+        if media.media_type == media::guess_media_type(&media.file) {
+            media.media_type = media_file.media_type;
+        }
+
+        media.duration = media.duration.or(media_file.duration);
+        media.width = media.width.or(media_file.width.map(|width| width as u32));
+        media.height = media.height.or(media_file.height.map(|height| height as u32));
+    }
+
+    Ok(())
+}
+
+// uri_names share the root with every other route, which all start with an underscore
+fn check_uri_name(uri_name: &str) -> Result<(), String> {
+    if uri_name.is_empty() || uri_name.starts_with('_') {
+        return Err("The URL name can't be empty or start with an underscore".to_owned());
+    }
+
+    if !uri_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("The URL name may only contain letters, numbers, dashes and underscores".to_owned());
+    }
+
+    Ok(())
+}
+
+fn now() -> i32 {
+    Utc::now().timestamp() as i32
+}
+
+#[get("/_admin/new", rank = 2)]
+fn new_page(_admin: Admin, templates: State<Templates>, config: State<Config>) -> Result<AdminResponse, Error> {
+    let post = models::Post {
+        id: 0,
+        uri_name: String::new(),
+        datetime: now(),
+        title: String::new(),
+        body: models::Body {
+            pieces: vec![]
+        }
+    };
+
+    Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, true, false, None)?))
+}
+
+#[derive(Debug, FromForm)]
+struct NewFromUploadQuery {
+    file: String
+}
+
+// "Night Drive (Extended Mix)" -> "night-drive-extended-mix"
+fn to_uri_name(title: &str) -> String {
+    title.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn to_media(media_file: &models::MediaFile, alt: Option<String>) -> models::Media {
+    models::Media {
+        file: media_file.file.clone(),
+        media_type: media_file.media_type.clone(),
+        duration: media_file.duration,
+        width: media_file.width.map(|width| width as u32),
+        height: media_file.height.map(|height| height as u32),
+        alt: alt,
+        caption: None
+    }
+}
+
+// a line for each tag the track has
+fn describe(media_file: &models::MediaFile) -> Option<String> {
+    let mut lines = Vec::new();
+
+    if let Some(ref artist) = media_file.artist {
+        lines.push(format!("By {}", html::escape(artist)));
+    }
+
+    match (&media_file.album, media_file.track_number) {
+        (Some(album), Some(track_number)) => lines.push(format!("Track {} on {}", track_number, html::escape(album))),
+        (Some(album), None) => lines.push(format!("From {}", html::escape(album))),
+        _ => {}
+    };
+
+    if let Some(year) = media_file.year {
+        lines.push(format!("Released {}", year));
+    }
+
+    if let Some(ref genre) = media_file.genre {
+        lines.push(html::escape(genre));
+    }
+
+    if let Some(ref isrc) = media_file.isrc {
+        lines.push(format!("ISRC {}", html::escape(isrc)));
+    }
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(format!("<p>{}</p>", lines.join("<br>\n")))
+    }
+}
+
+// starts a post with an upload, its cover art and a description from its tags
+#[get("/_admin/new?<query>", rank = 1)]
+fn new_from_upload(_admin: Admin, query: NewFromUploadQuery, templates: State<Templates>, config: State<Config>, database: Database) -> Result<AdminResponse, Error> {
+    let media_file = media::get_by_file(&database.conn, &query.file)
+        .map_err(Error::internal)?
+        .ok_or(Error::NotFound)?;
+
+    let title = media_file.title.clone().unwrap_or_else(|| {
+        Path::new(&media_file.filename)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+
+    let media = to_media(&media_file, None);
+
+    let mut pieces = vec![
+        if media_file.media_type.starts_with("video/") {
+            models::Piece::Video(media)
+        } else if media_file.media_type.starts_with("image/") {
+            models::Piece::Image(media)
+        } else {
+            models::Piece::Audio(media)
+        }
+    ];
+
+    let cover = match media_file.cover {
+        Some(ref cover) => media::get_by_file(&database.conn, cover).map_err(Error::internal)?,
+        None => None
+    };
+
+    if let Some(cover) = cover {
+        pieces.push(models::Piece::Image(to_media(&cover, Some(format!("Cover art for {}", media_file.album.as_ref().unwrap_or(&title))))));
+    }
+
+    if let Some(description) = describe(&media_file) {
+        pieces.push(models::Piece::Html(description));
+    }
+
+    let post = models::Post {
+        id: 0,
+        uri_name: to_uri_name(&title),
+        datetime: now(),
+        title: title,
+        body: models::Body {
+            pieces: pieces
+        }
+    };
+
+    Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, true, false, None)?))
+}
+
+#[post("/_admin/new", data = "<form>")]
+fn new(_admin: Admin, form: Form<PostForm>, templates: State<Templates>, config: State<Config>, database: Database) -> Result<AdminResponse, Error> {
+    let form = form.into_inner();
+    let mut post = form.to_post(now());
+
+    if let Err(error) = check_uri_name(&form.uri_name) {
+        return Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, true, false, Some(error))?));
+    }
+
+    if !form.save {
+        return Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, true, true, None)?));
+    }
+
+    if let Err(error) = check_media(&database, &mut post.body) {
+        return Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, true, false, Some(error))?));
+    }
+
+    let new_post = models::NewPost {
+        uri_name: post.uri_name.clone(),
+        title: post.title.clone(),
+        body: post.body
+    };
+
+    match activitypub::create_post(&config, &database.conn, &new_post) {
+        Ok(()) => Ok(to_index()),
+        Err(e) => match e.downcast_ref::<DieselError>() {
+            Some(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                let error = format!("There's already a post named {}", form.uri_name);
+                Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, true, false, Some(error))?))
+            },
+            _ => Err(Error::internal(e))
+        }
+    }
+}
+
+fn get_post(database: &Database, uri_name: &str) -> Result<models::Post, Error> {
+    schema::posts::table
+        .filter(schema::posts::uri_name.eq(uri_name))
+        .first::<models::Post>(&database.conn)
+        .optional()?
+        .ok_or(Error::NotFound)
+}
+
+#[get("/_admin/edit/<uri_name>")]
+fn edit_page(_admin: Admin, uri_name: String, templates: State<Templates>, config: State<Config>, database: Database) -> Result<AdminResponse, Error> {
+    let post = get_post(&database, &uri_name)?;
+
+    Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, false, false, None)?))
+}
+
+#[post("/_admin/edit/<uri_name>", data = "<form>")]
+fn edit(_admin: Admin, uri_name: String, form: Form<PostForm>, templates: State<Templates>, config: State<Config>, database: Database) -> Result<AdminResponse, Error> {
+    let existing = get_post(&database, &uri_name)?;
+
+    let mut form = form.into_inner();
+    form.uri_name = existing.uri_name;
+
+    let mut post = form.to_post(existing.datetime);
+
+    if !form.save {
+        return Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, false, true, None)?));
+    }
+
+    if let Err(error) = check_media(&database, &mut post.body) {
+        return Ok(AdminResponse::Page(html::admin_edit(&templates, &config, &post, false, false, Some(error))?));
+    }
+
+    activitypub::update_post(&config, &database.conn, &post.uri_name, &post.title, &post.body)
+        .map_err(Error::internal)?;
+
+    Ok(to_index())
+}
+
+#[post("/_admin/delete/<uri_name>")]
+fn delete(_admin: Admin, uri_name: String, config: State<Config>, database: Database) -> Result<AdminResponse, Error> {
+    get_post(&database, &uri_name)?;
+
+    activitypub::delete_post(&config, &database.conn, &uri_name)
+        .map_err(Error::internal)?;
+
+    Ok(to_index())
+}
+
+fn get_media_json(config: &Config, media_file: &models::MediaFile) -> Value {
+    json!({
+        "url": config.media_url(&media_file.file),
+        "file": media_file.file,
+        "hash": media_file.hash,
+        "filename": media_file.filename,
+        "media_type": media_file.media_type,
+        "size": media_file.size,
+        "width": media_file.width,
+        "height": media_file.height,
+        "duration": media_file.duration,
+        "title": media_file.title,
+        "artist": media_file.artist,
+        "album": media_file.album,
+        "track_number": media_file.track_number,
+        "year": media_file.year,
+        "genre": media_file.genre,
+        "isrc": media_file.isrc,
+        "cover": media_file.cover.as_ref().map(|cover| config.media_url(cover)),
+        "sample_rate": media_file.sample_rate,
+        "channels": media_file.channels,
+        "bitrate": media_file.bitrate,
+        "peaks": media_file.peaks.as_ref().map(|_| config.peaks_url(&media_file.hash))
+    })
+}
+
+// stores every file sent in a `file` field
+fn store_uploads(config: &Config, store: &MediaStore, database: &Database, content_type: &ContentType, data: Data) -> Result<Vec<models::MediaFile>, Error> {
+    let boundary = content_type.params()
+        .find(|&(key, _)| key == "boundary")
+        .map(|(_, value)| value.to_owned())
+        .ok_or(Error::BadRequest("No multipart boundary given".to_owned()))?;
+
+    let mut multipart = Multipart::with_body(data.open(), boundary);
+    let mut uploaded = Vec::new();
+
+    while let Some(mut field) = multipart.read_entry()? {
+        if &*field.headers.name != "file" {
+            continue;
+        }
+
+        // an empty file input still sends a field, without a filename
+        let filename = match field.headers.filename.clone() {
+            Some(ref filename) if !filename.is_empty() => filename.clone(),
+            _ => continue
+        };
+
+        let media_type = field.headers.content_type
+            .as_ref()
+            .map(|media_type| media_type.to_string());
+
+        let media_file = media::store(config, store, &database.conn, &mut field.data, &filename, media_type.as_ref().map(String::as_str))
+            .map_err(Error::internal)?;
+
+        uploaded.push(media_file);
+    }
+
+    Ok(uploaded)
+}
+
+#[post("/_admin/upload", data = "<data>")]
+fn upload(_admin: Admin, content_type: ContentType, data: Data, worker: State<InboxWorker>, templates: State<Templates>, config: State<Config>, store: State<Box<MediaStore>>, database: Database) -> Result<AdminResponse, Error> {
+    let uploaded = store_uploads(&config, &**store, &database, &content_type, data)?;
+
+    let notice = if uploaded.is_empty() {
+        "No files were uploaded".to_owned()
+    } else {
+        let files: Vec<&str> = uploaded.iter()
+            .map(|media_file| media_file.file.as_str())
+            .collect();
+
+        format!("Uploaded {}", files.join(", "))
+    };
+
+    render_index(&templates, &config, &worker, &database, Some(notice))
+}
+
+#[post("/_admin/media", data = "<data>")]
+fn upload_media(_admin: Admin, content_type: ContentType, data: Data, config: State<Config>, store: State<Box<MediaStore>>, database: Database) -> Result<Json<Value>, Error> {
+    let uploaded = store_uploads(&config, &**store, &database, &content_type, data)?;
+
+    Ok(Json(json!(uploaded.iter()
+        .map(|media_file| get_media_json(&config, media_file))
+        .collect::<Vec<_>>())))
+}
+
+#[post("/_admin/inbox/pause")]
+fn pause_inbox(_admin: Admin, worker: State<InboxWorker>) -> AdminResponse {
+    worker.pause();
+
+    to_index()
+}
+
+#[post("/_admin/inbox/resume")]
+fn resume_inbox(_admin: Admin, worker: State<InboxWorker>) -> AdminResponse {
+    worker.resume();
+
+    to_index()
+}
+
+#[error(401)]
+fn unauthorized() -> Redirect {
+    Redirect::to("/_admin/login")
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![login_page, login, logout, index, new_page, new_from_upload, new, edit_page, edit, delete, upload, upload_media, pause_inbox, resume_inbox]
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    errors![unauthorized]
+}
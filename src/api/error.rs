@@ -11,6 +11,7 @@ use ::rocket::{
 pub enum Error {
     NotFound,
     BadRequest(String),
+    Forbidden(String),
     Internal(String)
 }
 
@@ -19,6 +20,10 @@ impl Error {
         Error::BadRequest(format!("{:?}", e))
     }
     
+    pub fn forbidden<E: Debug>(e: E) -> Self {
+        Error::Forbidden(format!("{:?}", e))
+    }
+
     pub fn internal<E: Debug>(e: E) -> Self {
         Error::Internal(format!("{:?}", e))
     }
@@ -36,6 +41,7 @@ impl Error {
         match self {
             Error::NotFound => Status::NotFound,
             Error::BadRequest(_) => Status::BadRequest,
+            Error::Forbidden(_) => Status::Forbidden,
             Error::Internal(_) => Status::InternalServerError
         }
     }
@@ -60,6 +66,8 @@ impl<'r> Responder<'r> for Error {
                 "4w4 what's this?".to_owned(),
             Error::BadRequest(ref why) =>
                 format!("{}\n\n{}", "Bad request", why),
+            Error::Forbidden(ref why) =>
+                format!("{}\n\n{}", "Forbidden", why),
             Error::Internal(ref why) => 
                 format!("{}\n\n{}", "OOPSIE WOOPSIE!! Uwu We made a fucky wucky!! A wittle fucko boingo! The code monkeys at our headquarters are working VEWY HAWD to fix this!", why)
         };
@@ -0,0 +1,232 @@
+use ::std::path::Path;
+
+use ::chrono::offset::{TimeZone, Utc};
+use ::serde_json::Value;
+use ::tera::Tera;
+
+use api::error::Error;
+use config::Config;
+use media;
+use models;
+
+// Pages are rendered from the tera templates in JADWIGA_TEMPLATE_DIR, so an instance
+// can be rebranded without rebuilding. Files in its static/ directory are served at
+// `static_url`. Each template gets the `actor` and `static_url` plus:
+//
+// actor.html:     posts, newer_url, older_url
+// post.html:      post
+// tombstone.html: tombstone
+// admin/*.html:   the admin area, see the admin_ functions below
+//
+// piece.html is included once per body piece, with the piece in `piece`
+
+pub struct Templates {
+    tera: Tera
+}
+
+impl Templates {
+    pub fn load(config: &Config) -> Result<Self, ::failure::Error> {
+        let glob = Path::new(&config.template_dir).join("**").join("*.html");
+
+        let tera = Tera::new(&glob.to_string_lossy())
+            .map_err(|e| format_err!("Failed to load templates from {}: {:?}", config.template_dir, e))?;
+
+        Ok(Templates {
+            tera: tera
+        })
+    }
+
+    fn render(&self, config: &Config, name: &str, mut context: Value) -> Result<String, Error> {
+        context["static_url"] = json!(config.static_url(""));
+
+        self.tera.render(name, &context)
+            .map_err(Error::internal)
+    }
+}
+
+fn format_date(timestamp: i32) -> String {
+    Utc.timestamp(timestamp as i64, 0)
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c)
+        }
+    }
+
+    escaped
+}
+
+// media pieces are federated as attachments, so only their captions go in the content
+pub fn content(post: &models::Post) -> String {
+    let mut content = String::new();
+
+    for piece in post.body.pieces.iter() {
+        match piece {
+            models::Piece::Html(html) => {
+                content.push_str(html);
+            },
+            _ => {
+                if let Some(caption) = piece.media().and_then(|media| media.caption.as_ref()) {
+                    content.push_str(&format!("<p>{}</p>", escape(caption)));
+                }
+            }
+        };
+    }
+
+    content
+}
+
+// fields a piece doesn't have are left out rather than null, so `default` works on them
+fn get_media_piece(config: &Config, kind: &str, media: &models::Media) -> Value {
+    let mut piece = json!({
+        "kind": kind,
+        "url": config.media_url(&media.file),
+        "file": media.file,
+        "media_type": media.media_type
+    });
+
+    if let Some(duration) = media.duration {
+        piece["duration"] = json!(duration);
+    }
+
+    if let Some(width) = media.width {
+        piece["width"] = json!(width);
+    }
+
+    if let Some(height) = media.height {
+        piece["height"] = json!(height);
+    }
+
+    if let Some(ref alt) = media.alt {
+        piece["alt"] = json!(alt);
+    }
+
+    if let Some(ref caption) = media.caption {
+        piece["caption"] = json!(caption);
+    }
+
+    if kind == "audio" {
+        let hash = media::file_hash(&media.file);
+
+        piece["peaks_url"] = json!(config.peaks_url(hash));
+        piece["waveform_url"] = json!(config.waveform_url(hash));
+    }
+
+    piece
+}
+
+fn get_piece(config: &Config, piece: &models::Piece) -> Value {
+    match piece {
+        models::Piece::Html(html) => json!({
+            "kind": "html",
+            "html": html
+        }),
+        models::Piece::Audio(media) => get_media_piece(config, "audio", media),
+        models::Piece::Video(media) => get_media_piece(config, "video", media),
+        models::Piece::Image(media) => get_media_piece(config, "image", media)
+    }
+}
+
+fn get_actor(config: &Config) -> Value {
+    json!({
+        "url": config.actor_url(),
+        "username": config.actor_username,
+        "host": config.root_url.host_str().unwrap_or(""),
+        "name": config.actor_name,
+        "bio": config.actor_bio,
+        "icon": config.media_url("icon.jpg")
+    })
+}
+
+fn get_post(config: &Config, post: &models::Post) -> Value {
+    json!({
+        "uri_name": post.uri_name,
+        "url": config.post_url(&post.uri_name),
+        "title": post.title,
+        "published": format_date(post.datetime),
+        "pieces": post.body.pieces.iter().map(|piece| get_piece(config, piece)).collect::<Vec<_>>()
+    })
+}
+
+pub fn actor(templates: &Templates, config: &Config, posts: &[models::Post], newer_url: Option<String>, older_url: Option<String>) -> Result<String, Error> {
+    templates.render(config, "actor.html", json!({
+        "actor": get_actor(config),
+        "posts": posts.iter().map(|post| get_post(config, post)).collect::<Vec<_>>(),
+        "newer_url": newer_url,
+        "older_url": older_url
+    }))
+}
+
+pub fn post(templates: &Templates, config: &Config, post: &models::Post) -> Result<String, Error> {
+    templates.render(config, "post.html", json!({
+        "actor": get_actor(config),
+        "post": get_post(config, post)
+    }))
+}
+
+pub fn tombstone(templates: &Templates, config: &Config, tombstone: &models::Tombstone) -> Result<String, Error> {
+    templates.render(config, "tombstone.html", json!({
+        "actor": get_actor(config),
+        "tombstone": {
+            "url": config.post_url(&tombstone.uri_name),
+            "title": tombstone.title,
+            "published": format_date(tombstone.published),
+            "deleted": format_date(tombstone.deleted)
+        }
+    }))
+}
+
+pub fn admin_login(templates: &Templates, config: &Config, error: Option<&str>) -> Result<String, Error> {
+    templates.render(config, "admin/login.html", json!({
+        "actor": get_actor(config),
+        "error": error
+    }))
+}
+
+pub fn admin_index(templates: &Templates, config: &Config, posts: &[models::Post], media_files: &[models::MediaFile], inbox_paused: bool, notice: Option<String>) -> Result<String, Error> {
+    let media_files: Vec<Value> = media_files.iter()
+        .map(|media_file| json!({
+            "url": config.media_url(&media_file.file),
+            "file": media_file.file,
+            "filename": media_file.filename,
+            "media_type": media_file.media_type,
+            "size": media_file.size,
+            "uploaded": format_date(media_file.uploaded),
+            "title": media_file.title,
+            "artist": media_file.artist,
+            "duration": media_file.duration.map(|duration| format!("{}:{:02}", duration as u64 / 60, duration as u64 % 60)),
+            "bitrate": media_file.bitrate.map(|bitrate| bitrate / 1000),
+            "sample_rate": media_file.sample_rate,
+            "channels": media_file.channels
+        }))
+        .collect();
+
+    templates.render(config, "admin/index.html", json!({
+        "actor": get_actor(config),
+        "posts": posts.iter().map(|post| get_post(config, post)).collect::<Vec<_>>(),
+        "media_files": media_files,
+        "inbox_paused": inbox_paused,
+        "notice": notice
+    }))
+}
+
+pub fn admin_edit(templates: &Templates, config: &Config, post: &models::Post, is_new: bool, preview: bool, error: Option<String>) -> Result<String, Error> {
+    templates.render(config, "admin/edit.html", json!({
+        "actor": get_actor(config),
+        "post": get_post(config, post),
+        "is_new": is_new,
+        "preview": preview,
+        "error": error
+    }))
+}
@@ -0,0 +1,340 @@
+use ::std::cmp;
+use ::std::io::{self, Read, Seek, SeekFrom};
+use ::std::path::PathBuf;
+
+use ::chrono::{DateTime, TimeZone, Utc};
+use ::rocket::{
+    Outcome, Request, Response, Route,
+    http::{ContentType, Status},
+    request::{self, FromRequest, State},
+    response::{self, content, Redirect, Responder}
+};
+
+use api::error::Error;
+use db::Database;
+use media;
+use models;
+use store::{MediaStore, Reader};
+use waveform;
+
+// Media is served with byte ranges so players can seek without downloading the
+// whole file:
+//
+// Range: bytes=1000-1999    -> 206, Content-Range: bytes 1000-1999/<length>
+// Range: bytes=-500         -> the last 500 bytes
+// Range: bytes=0-9,20-29    -> 416, multiple ranges aren't supported
+//
+// If-Range, If-None-Match and If-Modified-Since are checked against the ETag, which
+// is the content hash for uploads, and the file's modification time in the store.
+//
+// Tracks also have their waveform at /_media/<hash>/peaks.json, drawn at
+// /_media/<hash>/waveform.png once the waveform worker has been through them.
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+#[derive(Debug)]
+pub struct MediaHeaders {
+    range: Option<String>,
+    if_range: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for MediaHeaders {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        let get = |name: &str| headers.get_one(name).map(|value| value.to_owned());
+
+        Outcome::Success(MediaHeaders {
+            range: get("Range"),
+            if_range: get("If-Range"),
+            if_none_match: get("If-None-Match"),
+            if_modified_since: get("If-Modified-Since")
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    // inclusive on both ends, like Content-Range
+    Satisfiable(u64, u64),
+    Unsatisfiable,
+    Multiple,
+    // malformed ranges are ignored and the whole file is sent
+    Invalid
+}
+
+fn parse_range(header: &str, length: u64) -> ByteRange {
+    let spec = match header.trim().splitn(2, '=').collect::<Vec<_>>().as_slice() {
+        [unit, spec] if unit.trim().eq_ignore_ascii_case("bytes") => spec.trim().to_owned(),
+        _ => return ByteRange::Invalid
+    };
+
+    if spec.contains(',') {
+        return ByteRange::Multiple;
+    }
+
+    let (first, last) = match spec.splitn(2, '-').collect::<Vec<_>>().as_slice() {
+        [first, last] => (first.trim().to_owned(), last.trim().to_owned()),
+        _ => return ByteRange::Invalid
+    };
+
+    match (first.parse::<u64>().ok(), last.parse::<u64>().ok()) {
+        // bytes=-500
+        (None, Some(suffix)) if first.is_empty() => {
+            if suffix == 0 || length == 0 {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Satisfiable(length.saturating_sub(suffix), length - 1)
+            }
+        },
+        // bytes=1000-
+        (Some(start), None) if last.is_empty() => {
+            if start >= length {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Satisfiable(start, length - 1)
+            }
+        },
+        // bytes=1000-1999
+        (Some(start), Some(end)) if start <= end => {
+            if start >= length {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Satisfiable(start, cmp::min(end, length - 1))
+            }
+        },
+        _ => ByteRange::Invalid
+    }
+}
+
+fn format_http_date(datetime: &DateTime<Utc>) -> String {
+    datetime.format(HTTP_DATE).to_string()
+}
+
+fn parse_http_date(text: &str) -> Option<DateTime<Utc>> {
+    Utc.datetime_from_str(text.trim(), HTTP_DATE).ok()
+}
+
+// weak validators (W/"...") never match If-Range, which needs a strong comparison
+fn etag_matches(header: &str, etag: &str, weak: bool) -> bool {
+    header.split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag == etag || (weak && tag.trim_left_matches("W/") == etag))
+}
+
+// Rocket sizes bodies by seeking to their end and back, which a stream from the media
+// store can't do, so this answers those two seeks with the length it already knows
+struct SizedBody {
+    reader: Reader,
+    length: u64
+}
+
+impl Read for SizedBody {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buffer)
+    }
+}
+
+impl Seek for SizedBody {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match position {
+            SeekFrom::End(0) => Ok(self.length),
+            SeekFrom::Start(0) => Ok(0),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Media bodies can't seek"))
+        }
+    }
+}
+
+pub struct FileResponse {
+    status: Status,
+    content_type: String,
+    etag: String,
+    last_modified: DateTime<Utc>,
+    content_range: Option<String>,
+    body: Option<SizedBody>
+}
+
+impl<'r> Responder<'r> for FileResponse {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        let mut response = Response::build();
+
+        response
+            .status(self.status)
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("ETag", self.etag)
+            .raw_header("Last-Modified", format_http_date(&self.last_modified));
+
+        if let Some(content_range) = self.content_range {
+            response.raw_header("Content-Range", content_range);
+        }
+
+        if let Some(body) = self.body {
+            let content_type = self.content_type.parse::<ContentType>()
+                .unwrap_or(ContentType::Binary);
+
+            response
+                .header(content_type)
+                .sized_body(body);
+        }
+
+        response.ok()
+    }
+}
+
+// stores with their own urls (S3 with redirects on) serve the file themselves
+pub enum MediaResponse {
+    File(FileResponse),
+    Redirect(Redirect)
+}
+
+impl<'r> Responder<'r> for MediaResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            MediaResponse::File(file) => file.respond_to(request),
+            MediaResponse::Redirect(redirect) => redirect.respond_to(request)
+        }
+    }
+}
+
+fn get_media(store: &MediaStore, database: &Database, file: PathBuf, headers: &MediaHeaders) -> Result<MediaResponse, Error> {
+    let file_name = file.to_str()
+        .ok_or(Error::NotFound)?
+        .to_owned();
+
+    // Rocket has already refused ".." and hidden segments; S3 keys are flat, though
+    if file.components().count() != 1 && !store.nested_keys() {
+        return Err(Error::NotFound);
+    }
+
+    if let Some(url) = store.redirect_url(&file_name).map_err(Error::internal)? {
+        return Ok(MediaResponse::Redirect(Redirect::temporary(&url)));
+    }
+
+    let stat = store.stat(&file_name)
+        .map_err(Error::internal)?
+        .ok_or(Error::NotFound)?;
+
+    let length = stat.length;
+    let last_modified = stat.modified;
+
+    // files put in the store by hand have no record, so fall back to their extension
+    let media_file = media::get_by_file(&database.conn, &file_name)
+        .map_err(Error::internal)?;
+
+    let (etag, content_type) = match media_file {
+        Some(media_file) => (format!("\"{}\"", media_file.hash), media_file.media_type),
+        None => (format!("\"{:x}-{:x}\"", last_modified.timestamp(), length), media::guess_media_type(&file_name).to_owned())
+    };
+
+    let mut response = FileResponse {
+        status: Status::Ok,
+        content_type: content_type,
+        etag: etag,
+        last_modified: last_modified,
+        content_range: None,
+        body: None
+    };
+
+    let not_modified = match (&headers.if_none_match, &headers.if_modified_since) {
+        (Some(if_none_match), _) => etag_matches(if_none_match, &response.etag, true),
+        (None, Some(if_modified_since)) => parse_http_date(if_modified_since)
+            .map(|since| last_modified <= since)
+            .unwrap_or(false),
+        (None, None) => false
+    };
+
+    if not_modified {
+        response.status = Status::NotModified;
+        return Ok(MediaResponse::File(response));
+    }
+
+    // a stale If-Range means the client's partial copy is out of date, so it gets everything
+    let range_applies = match headers.if_range {
+        Some(ref if_range) if if_range.trim().starts_with('"') || if_range.trim().starts_with("W/") =>
+            etag_matches(if_range, &response.etag, false),
+        Some(ref if_range) => parse_http_date(if_range)
+            .map(|date| date == last_modified)
+            .unwrap_or(false),
+        None => true
+    };
+
+    let range = match headers.range {
+        Some(ref range) if range_applies => parse_range(range, length),
+        _ => ByteRange::Invalid
+    };
+
+    match range {
+        ByteRange::Satisfiable(start, end) => {
+            response.status = Status::PartialContent;
+            response.content_range = Some(format!("bytes {}-{}/{}", start, end, length));
+            response.body = Some(SizedBody {
+                reader: store.get_range(&file_name, start, end - start + 1).map_err(Error::internal)?,
+                length: end - start + 1
+            });
+        },
+        ByteRange::Unsatisfiable | ByteRange::Multiple => {
+            response.status = Status::RangeNotSatisfiable;
+            response.content_range = Some(format!("bytes */{}", length));
+        },
+        ByteRange::Invalid => {
+            response.body = Some(SizedBody {
+                reader: store.get(&file_name).map_err(Error::internal)?,
+                length: length
+            });
+        }
+    };
+
+    Ok(MediaResponse::File(response))
+}
+
+#[get("/_media/<file..>", rank = 2)]
+fn media(file: PathBuf, headers: MediaHeaders, store: State<Box<MediaStore>>, database: Database) -> Result<MediaResponse, Error> {
+    get_media(&**store, &database, file, &headers)
+}
+
+fn get_track(database: &Database, hash: &str) -> Result<models::MediaFile, Error> {
+    media::get_by_hash(&database.conn, hash)
+        .map_err(Error::internal)?
+        .filter(|media_file| media_file.media_type.starts_with("audio/"))
+        .ok_or(Error::NotFound)
+}
+
+#[get("/_media/<hash>/peaks.json", rank = 1)]
+fn peaks(hash: String, database: Database) -> Result<content::Json<String>, Error> {
+    get_track(&database, &hash)?
+        .peaks
+        .map(content::Json)
+        .ok_or(Error::NotFound)
+}
+
+pub enum WaveformResponse {
+    Drawn(MediaResponse),
+    // a flat line until the worker has got to the track, which mustn't be cached
+    Pending(Vec<u8>)
+}
+
+impl<'r> Responder<'r> for WaveformResponse {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            WaveformResponse::Drawn(drawn) => drawn.respond_to(request),
+            WaveformResponse::Pending(image) => Response::build_from(content::Png(image).respond_to(request)?)
+                .raw_header("Cache-Control", "no-cache")
+                .ok()
+        }
+    }
+}
+
+#[get("/_media/<hash>/waveform.png", rank = 1)]
+fn waveform_image(hash: String, headers: MediaHeaders, store: State<Box<MediaStore>>, database: Database) -> Result<WaveformResponse, Error> {
+    match get_track(&database, &hash)?.waveform {
+        Some(file) => Ok(WaveformResponse::Drawn(get_media(&**store, &database, PathBuf::from(file), &headers)?)),
+        None => Ok(WaveformResponse::Pending(waveform::render_png(None).map_err(Error::internal)?))
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![media, peaks, waveform_image]
+}
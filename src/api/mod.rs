@@ -1,3 +1,7 @@
 pub mod activitypub;
+pub mod admin;
 pub mod error;
+pub mod html;
+pub mod media;
+pub mod negotiate;
 pub mod webfinger;
@@ -0,0 +1,103 @@
+use ::rocket::{
+    Request, Response, Outcome,
+    http::{ContentType, MediaType, Status},
+    request::{self, FromRequest},
+    response::{self, content, Responder}
+};
+use ::rocket_contrib::Json;
+use ::serde_json::Value;
+
+use ns;
+
+// Every public URL serves both fediverse software and people with browsers:
+//
+// Accept: application/activity+json
+// Accept: application/ld+json; profile="https://www.w3.org/ns/activitystreams"
+//
+// get the ActivityStreams document, while text/html gets a rendered page
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    ActivityPub,
+    Html
+}
+
+fn is_activitypub(media_type: &MediaType) -> bool {
+    if media_type.top() != "application" {
+        return false;
+    }
+
+    if media_type.sub() == "activity+json" {
+        return true;
+    }
+
+    media_type.sub() == "ld+json" && media_type.params()
+        .filter(|&(key, _)| key == "profile")
+        .all(|(_, value)| value.split(' ').any(|profile| profile == ns::ACTIVITYSTREAMS))
+}
+
+fn is_html(media_type: &MediaType) -> bool {
+    media_type.top() == "text" && media_type.sub() == "html"
+}
+
+// without a usable Accept header, default to the json other servers are asking for
+impl<'a, 'r> FromRequest<'a, 'r> for Format {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let accept = match request.accept() {
+            Some(accept) => accept,
+            None => return Outcome::Success(Format::ActivityPub)
+        };
+
+        let mut best: Option<(f32, Format)> = None;
+
+        for media_type in accept.media_types() {
+            let format = if is_activitypub(media_type.media_type()) {
+                Format::ActivityPub
+            } else if is_html(media_type.media_type()) {
+                Format::Html
+            } else {
+                continue;
+            };
+
+            let weight = media_type.weight_or(1.0);
+
+            best = match best {
+                Some((best_weight, _)) if best_weight >= weight => best,
+                _ => Some((weight, format))
+            };
+        }
+
+        Outcome::Success(best.map(|(_, format)| format).unwrap_or(Format::ActivityPub))
+    }
+}
+
+#[derive(Debug)]
+pub enum Negotiated {
+    ActivityPub(Status, Value),
+    Html(Status, String)
+}
+
+impl<'r> Responder<'r> for Negotiated {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let response = match self {
+            Negotiated::ActivityPub(status, json) => {
+                Response::build_from(Json(json).respond_to(request)?)
+                    .status(status)
+                    .header(ContentType::new("application", "activity+json"))
+                    .finalize()
+            },
+            Negotiated::Html(status, html) => {
+                Response::build_from(content::Html(html).respond_to(request)?)
+                    .status(status)
+                    .finalize()
+            }
+        };
+
+        // caches must not hand a browser the json, or a server the html
+        Response::build_from(response)
+            .raw_header("Vary", "Accept")
+            .ok()
+    }
+}
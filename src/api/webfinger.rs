@@ -30,6 +30,11 @@ fn find(query: Query, config: State<Config>) -> Result<Json<Webfinger>, Error>{
                 rel: "self".to_owned(),
                 mime_type: Some("application/activity+json".to_owned()),
                 href: config.actor_url()
+            },
+            Link {
+                rel: "http://webfinger.net/rel/profile-page".to_owned(),
+                mime_type: Some("text/html".to_owned()),
+                href: config.actor_url()
             }
         ]
     }))
@@ -0,0 +1,831 @@
+use ::std::cmp;
+use ::std::fs::File;
+use ::std::io::{self, Read, Seek, SeekFrom};
+use ::std::mem;
+use ::std::path::Path;
+
+use ::base64;
+
+// Tags and technical details of uploaded tracks, read from their headers without
+// decoding any audio:
+//
+// MP3:        ID3v2.3/2.4 tags (or ID3v1), the first frame header and Xing/Info/VBRI frame counts
+// FLAC:       STREAMINFO, VORBIS_COMMENT and PICTURE metadata blocks
+// Ogg, Opus:  the identification and comment headers, and the last page's granule position
+//
+// Whatever can't be found is left as None.
+
+// tags bigger than this (cover art included) are skipped rather than read into memory
+const MAX_TAG_SIZE: u64 = 16 * 1024 * 1024;
+const FRAME_SEARCH_SIZE: u64 = 64 * 1024;
+const OGG_TAIL_SIZE: u64 = 64 * 1024;
+const FRONT_COVER: u32 = 3;
+
+const MPEG1_BITRATES: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MPEG2_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+const ID3V1_GENRES: [&str; 80] = [
+    "Blues", "Classic Rock", "Country", "Dance", "Disco", "Funk", "Grunge", "Hip-Hop",
+    "Jazz", "Metal", "New Age", "Oldies", "Other", "Pop", "R&B", "Rap",
+    "Reggae", "Rock", "Techno", "Industrial", "Alternative", "Ska", "Death Metal", "Pranks",
+    "Soundtrack", "Euro-Techno", "Ambient", "Trip-Hop", "Vocal", "Jazz+Funk", "Fusion", "Trance",
+    "Classical", "Instrumental", "Acid", "House", "Game", "Sound Clip", "Gospel", "Noise",
+    "AlternRock", "Bass", "Soul", "Punk", "Space", "Meditative", "Instrumental Pop", "Instrumental Rock",
+    "Ethnic", "Gothic", "Darkwave", "Techno-Industrial", "Electronic", "Pop-Folk", "Eurodance", "Dream",
+    "Southern Rock", "Comedy", "Cult", "Gangsta", "Top 40", "Christian Rap", "Pop/Funk", "Jungle",
+    "Native American", "Cabaret", "New Wave", "Psychadelic", "Rave", "Showtunes", "Trailer", "Lo-Fi",
+    "Tribal", "Acid Punk", "Acid Jazz", "Polka", "Retro", "Musical", "Rock & Roll", "Hard Rock"
+];
+
+#[derive(Debug, Clone)]
+pub struct Picture {
+    // as in ID3 and FLAC, 3 is the front cover
+    pub picture_type: u32,
+    pub media_type: String,
+    pub data: Vec<u8>
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AudioInfo {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<i32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>,
+    pub cover: Option<Picture>,
+    // seconds
+    pub duration: Option<f64>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    // bits per second, averaged over the file for VBR
+    pub bitrate: Option<i32>
+}
+
+// the first non-empty value for a tag wins
+fn set_text(field: &mut Option<String>, value: Option<String>) {
+    if field.is_none() {
+        *field = value
+            .map(|value| value.trim_matches(|c: char| c == '\u{0}' || c.is_whitespace()).to_owned())
+            .filter(|value| !value.is_empty());
+    }
+}
+
+impl AudioInfo {
+    // a front cover beats any other picture
+    fn set_cover(&mut self, picture: Option<Picture>) {
+        let picture = match picture {
+            Some(picture) => picture,
+            None => return
+        };
+
+        let replace = match self.cover {
+            None => true,
+            Some(ref cover) => cover.picture_type != FRONT_COVER && picture.picture_type == FRONT_COVER
+        };
+
+        if replace {
+            self.cover = Some(picture);
+        }
+    }
+
+    fn set_average_bitrate(&mut self, audio_bytes: u64) {
+        if self.bitrate.is_some() {
+            return;
+        }
+
+        self.bitrate = match self.duration {
+            Some(duration) if duration > 0.0 => Some((audio_bytes as f64 * 8.0 / duration) as i32),
+            _ => None
+        };
+    }
+}
+
+// bounds-checked reads from a tag or header already in memory
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ByteReader {
+            bytes: bytes,
+            position: 0
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(length)?;
+        let taken = self.bytes.get(self.position..end)?;
+        self.position = end;
+
+        Some(taken)
+    }
+
+    fn u32_be(&mut self) -> Option<u32> {
+        self.take(4).map(u32_be)
+    }
+
+    fn u32_le(&mut self) -> Option<u32> {
+        self.take(4).map(u32_le)
+    }
+}
+
+fn u32_be(bytes: &[u8]) -> u32 {
+    bytes[..4].iter().fold(0, |value, &byte| value << 8 | byte as u32)
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    bytes[..4].iter().rev().fold(0, |value, &byte| value << 8 | byte as u32)
+}
+
+fn u64_le(bytes: &[u8]) -> u64 {
+    bytes[..8].iter().rev().fold(0, |value, &byte| value << 8 | byte as u64)
+}
+
+// ID3v2 sizes use 7 bits per byte so they never look like an MPEG frame sync
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes[..4].iter().fold(0, |value, &byte| value << 7 | (byte & 0x7F) as u32)
+}
+
+fn read_at(file: &mut File, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut buffer = Vec::new();
+    file.by_ref().take(length).read_to_end(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+// the leading digits of "3/12" or "2018-07-28"
+fn parse_number(text: &str) -> Option<i32> {
+    let digits: String = text.trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+
+    digits.parse().ok()
+}
+
+fn id3v1_genre(index: usize) -> Option<String> {
+    ID3V1_GENRES.get(index).map(|genre| (*genre).to_owned())
+}
+
+fn picture_media_type(declared: &str, data: &[u8]) -> Option<String> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some("image/jpeg".to_owned());
+    }
+
+    if data.starts_with(b"\x89PNG") {
+        return Some("image/png".to_owned());
+    }
+
+    if data.starts_with(b"GIF8") {
+        return Some("image/gif".to_owned());
+    }
+
+    match declared.trim().to_lowercase().as_str() {
+        "image/jpeg" | "image/jpg" => Some("image/jpeg".to_owned()),
+        declared if declared.starts_with("image/") => Some(declared.to_owned()),
+        _ => None
+    }
+}
+
+// undoes ID3 unsynchronisation, which puts a 0x00 after every 0xFF
+fn resynchronise(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut previous = 0;
+
+    for &byte in bytes {
+        if !(previous == 0xFF && byte == 0x00) {
+            result.push(byte);
+        }
+
+        previous = byte;
+    }
+
+    result
+}
+
+fn decode_id3_text(encoding: u8, bytes: &[u8]) -> String {
+    match encoding {
+        // ISO-8859-1 maps straight onto the first 256 code points
+        0 => bytes.iter().map(|&byte| byte as char).collect(),
+        // UTF-16 with a byte order mark, or big-endian without one
+        1 | 2 => {
+            let (little_endian, bytes) = if bytes.starts_with(&[0xFF, 0xFE]) {
+                (true, &bytes[2..])
+            } else if bytes.starts_with(&[0xFE, 0xFF]) {
+                (false, &bytes[2..])
+            } else {
+                (false, bytes)
+            };
+
+            let units: Vec<u16> = bytes.chunks(2)
+                .filter(|pair| pair.len() == 2)
+                .map(|pair| if little_endian {
+                    (pair[1] as u16) << 8 | pair[0] as u16
+                } else {
+                    (pair[0] as u16) << 8 | pair[1] as u16
+                })
+                .collect();
+
+            String::from_utf16_lossy(&units)
+        },
+        _ => String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+// ID3v2.4 separates multiple values with nulls; only the first is kept
+fn id3_text_frame(data: &[u8]) -> Option<String> {
+    let (&encoding, text) = data.split_first()?;
+
+    decode_id3_text(encoding, text)
+        .split('\u{0}')
+        .next()
+        .map(|text| text.trim().to_owned())
+        .filter(|text| !text.is_empty())
+}
+
+// v2.3 refers to ID3v1 genres as "(17)", optionally followed by a refinement, and
+// v2.4 as plain "17"
+fn id3_genre(text: &str) -> Option<String> {
+    let text = text.trim();
+
+    if text.starts_with('(') && !text.starts_with("((") {
+        if let Some(close) = text.find(')') {
+            let refinement = text[close + 1..].trim();
+
+            if !refinement.is_empty() {
+                return Some(refinement.to_owned());
+            }
+
+            return text[1..close].parse().ok().and_then(id3v1_genre);
+        }
+    }
+
+    match text.parse() {
+        Ok(index) => id3v1_genre(index),
+        Err(_) => Some(text.trim_left_matches('(').to_owned())
+    }
+}
+
+fn id3_picture(data: &[u8]) -> Option<Picture> {
+    let (&encoding, rest) = data.split_first()?;
+
+    let mime_end = rest.iter().position(|&byte| byte == 0)?;
+    let mime = String::from_utf8_lossy(&rest[..mime_end]).into_owned();
+
+    let (&picture_type, rest) = rest[mime_end + 1..].split_first()?;
+
+    // the description ends with a null, two for UTF-16
+    let description_end = if encoding == 1 || encoding == 2 {
+        rest.chunks(2).position(|pair| *pair == [0, 0])? * 2 + 2
+    } else {
+        rest.iter().position(|&byte| byte == 0)? + 1
+    };
+
+    let image = rest.get(description_end..)?;
+
+    Some(Picture {
+        picture_type: picture_type as u32,
+        media_type: picture_media_type(&mime, image)?,
+        data: image.to_vec()
+    })
+}
+
+fn read_id3v2_frame(id: &[u8], data: &[u8], info: &mut AudioInfo) {
+    match id {
+        b"TIT2" => set_text(&mut info.title, id3_text_frame(data)),
+        b"TPE1" => set_text(&mut info.artist, id3_text_frame(data)),
+        b"TALB" => set_text(&mut info.album, id3_text_frame(data)),
+        b"TCON" => set_text(&mut info.genre, id3_text_frame(data).and_then(|text| id3_genre(&text))),
+        b"TSRC" => set_text(&mut info.isrc, id3_text_frame(data)),
+        b"TRCK" => {
+            info.track_number = info.track_number.or(id3_text_frame(data).and_then(|text| parse_number(&text)));
+        },
+        b"TYER" | b"TDRC" => {
+            info.year = info.year.or(id3_text_frame(data).and_then(|text| parse_number(&text)));
+        },
+        // milliseconds; only used if the frame headers don't give a duration
+        b"TLEN" => {
+            info.duration = info.duration.or(id3_text_frame(data)
+                .and_then(|text| text.parse::<f64>().ok())
+                .map(|milliseconds| milliseconds / 1000.0));
+        },
+        b"APIC" => info.set_cover(id3_picture(data)),
+        _ => {}
+    }
+}
+
+fn read_id3v2_frames(tag: &[u8], major: u8, flags: u8, info: &mut AudioInfo) -> Option<()> {
+    let mut reader = ByteReader::new(tag);
+
+    // the extended header only holds things like CRCs
+    if flags & 0x40 != 0 {
+        let size = reader.take(4)?;
+
+        let remaining = if major == 4 {
+            (syncsafe(size) as usize).checked_sub(4)?
+        } else {
+            u32_be(size) as usize
+        };
+
+        reader.take(remaining)?;
+    }
+
+    while let Some(header) = reader.take(10) {
+        // padding
+        if header[0] == 0 {
+            break;
+        }
+
+        let id = &header[..4];
+        let size = if major == 4 { syncsafe(&header[4..8]) } else { u32_be(&header[4..8]) };
+        let format_flags = header[9];
+
+        let resynchronised;
+        let mut data = reader.take(size as usize)?;
+
+        if major == 4 {
+            // compressed or encrypted
+            if format_flags & 0x0C != 0 {
+                continue;
+            }
+
+            // grouping identity, then data length indicator
+            if format_flags & 0x40 != 0 {
+                data = data.get(1..)?;
+            }
+
+            if format_flags & 0x01 != 0 {
+                data = data.get(4..)?;
+            }
+
+            if format_flags & 0x02 != 0 {
+                resynchronised = resynchronise(data);
+                data = &resynchronised;
+            }
+        } else {
+            // compressed or encrypted
+            if format_flags & 0xC0 != 0 {
+                continue;
+            }
+
+            if format_flags & 0x20 != 0 {
+                data = data.get(1..)?;
+            }
+        }
+
+        read_id3v2_frame(id, data, info);
+    }
+
+    Some(())
+}
+
+// returns where the tag ends, which is 0 if there isn't one
+fn read_id3v2(file: &mut File, info: &mut AudioInfo) -> io::Result<u64> {
+    let header = read_at(file, 0, 10)?;
+
+    if header.len() < 10 || &header[..3] != b"ID3" {
+        return Ok(0);
+    }
+
+    let major = header[3];
+    let flags = header[5];
+    let size = syncsafe(&header[6..10]) as u64;
+
+    // a v2.4 footer repeats the header after the tag
+    let end = 10 + size + if flags & 0x10 != 0 { 10 } else { 0 };
+
+    // v2.2 has three letter frame ids, and isn't worth supporting
+    if (major != 3 && major != 4) || size > MAX_TAG_SIZE {
+        return Ok(end);
+    }
+
+    let mut tag = read_at(file, 10, size)?;
+
+    // v2.3 unsynchronises the whole tag, v2.4 each frame
+    if major == 3 && flags & 0x80 != 0 {
+        tag = resynchronise(&tag);
+    }
+
+    read_id3v2_frames(&tag, major, flags, info);
+
+    Ok(end)
+}
+
+// returns whether there's a tag, which takes up the last 128 bytes of the file
+fn read_id3v1(file: &mut File, length: u64, info: &mut AudioInfo) -> io::Result<bool> {
+    if length < 128 {
+        return Ok(false);
+    }
+
+    let tag = read_at(file, length - 128, 128)?;
+
+    if tag.len() < 128 || &tag[..3] != b"TAG" {
+        return Ok(false);
+    }
+
+    let field = |start: usize, end: usize| Some(decode_id3_text(0, &tag[start..end]));
+
+    set_text(&mut info.title, field(3, 33));
+    set_text(&mut info.artist, field(33, 63));
+    set_text(&mut info.album, field(63, 93));
+
+    info.year = info.year.or(parse_number(&decode_id3_text(0, &tag[93..97])));
+
+    // ID3v1.1 puts the track number at the end of the comment
+    if tag[125] == 0 && tag[126] != 0 {
+        info.track_number = info.track_number.or(Some(tag[126] as i32));
+    }
+
+    set_text(&mut info.genre, id3v1_genre(tag[127] as usize));
+
+    Ok(true)
+}
+
+#[derive(Debug)]
+struct FrameHeader {
+    // bits per second
+    bitrate: u32,
+    sample_rate: u32,
+    channels: u32,
+    samples: u32,
+    length: usize,
+    side_info: usize
+}
+
+// only MPEG Layer III, which is what MP3 is
+fn parse_frame_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    // 3 is MPEG-1, 2 MPEG-2 and 0 MPEG-2.5
+    let version = (bytes[1] >> 3) & 3;
+    let layer = (bytes[1] >> 1) & 3;
+
+    if version == 1 || layer != 1 {
+        return None;
+    }
+
+    let bitrate_index = (bytes[2] >> 4) as usize;
+    let sample_rate_index = ((bytes[2] >> 2) & 3) as usize;
+    let padding = ((bytes[2] >> 1) & 1) as usize;
+
+    if bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+
+    let mpeg1 = version == 3;
+    let mono = bytes[3] >> 6 == 3;
+
+    let bitrate = if mpeg1 { MPEG1_BITRATES[bitrate_index] } else { MPEG2_BITRATES[bitrate_index] } * 1000;
+
+    let sample_rate = match version {
+        3 => [44100, 48000, 32000][sample_rate_index],
+        2 => [22050, 24000, 16000][sample_rate_index],
+        _ => [11025, 12000, 8000][sample_rate_index]
+    };
+
+    let samples = if mpeg1 { 1152 } else { 576 };
+
+    Some(FrameHeader {
+        bitrate: bitrate,
+        sample_rate: sample_rate,
+        channels: if mono { 1 } else { 2 },
+        samples: samples,
+        length: (samples / 8 * bitrate / sample_rate) as usize + padding,
+        side_info: match (mpeg1, mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9
+        }
+    })
+}
+
+// VBR encoders put the number of frames in a Xing (or Info) or VBRI header in place
+// of the first frame's audio
+fn vbr_frames(frame: &[u8], header: &FrameHeader) -> Option<u32> {
+    let xing = 4 + header.side_info;
+
+    match frame.get(xing..xing + 4) {
+        Some(b"Xing") | Some(b"Info") => {
+            let flags = u32_be(frame.get(xing + 4..xing + 8)?);
+
+            if flags & 1 != 0 {
+                return Some(u32_be(frame.get(xing + 8..xing + 12)?));
+            }
+
+            return None;
+        },
+        _ => {}
+    };
+
+    match frame.get(36..40) {
+        Some(b"VBRI") => Some(u32_be(frame.get(50..54)?)),
+        _ => None
+    }
+}
+
+fn read_mp3(file: &mut File, length: u64, info: &mut AudioInfo) -> Result<(), ::failure::Error> {
+    let audio_start = read_id3v2(file, info)?;
+    let audio_end = if read_id3v1(file, length, info)? { length - 128 } else { length };
+
+    let buffer = read_at(file, audio_start, FRAME_SEARCH_SIZE)?;
+
+    // a frame sync can turn up by chance, so the frame after it has to check out too
+    let found = (0..buffer.len())
+        .filter_map(|offset| parse_frame_header(&buffer[offset..]).map(|header| (offset, header)))
+        .find(|(offset, header)| match buffer.get(offset + header.length..) {
+            Some(next) if next.len() >= 4 => parse_frame_header(next).is_some(),
+            _ => true
+        });
+
+    let (offset, header) = found
+        .ok_or(format_err!("No MPEG audio frames found"))?;
+
+    let audio_bytes = audio_end.saturating_sub(audio_start + offset as u64);
+
+    info.sample_rate = Some(header.sample_rate as i32);
+    info.channels = Some(header.channels as i32);
+
+    match vbr_frames(&buffer[offset..], &header) {
+        Some(frames) => {
+            info.duration = Some(frames as f64 * header.samples as f64 / header.sample_rate as f64);
+            info.set_average_bitrate(audio_bytes);
+        },
+        None => {
+            info.duration = Some(audio_bytes as f64 * 8.0 / header.bitrate as f64);
+            info.bitrate = Some(header.bitrate as i32);
+        }
+    };
+
+    Ok(())
+}
+
+fn read_flac_picture(block: &[u8]) -> Option<Picture> {
+    let mut reader = ByteReader::new(block);
+
+    let picture_type = reader.u32_be()?;
+    let mime_length = reader.u32_be()? as usize;
+    let mime = String::from_utf8_lossy(reader.take(mime_length)?).into_owned();
+    let description_length = reader.u32_be()? as usize;
+    reader.take(description_length)?;
+
+    // width, height, colour depth and palette size
+    reader.take(16)?;
+
+    let data_length = reader.u32_be()? as usize;
+    let data = reader.take(data_length)?;
+
+    Some(Picture {
+        picture_type: picture_type,
+        media_type: picture_media_type(&mime, data)?,
+        data: data.to_vec()
+    })
+}
+
+// shared by FLAC, Vorbis and Opus: a vendor string, then KEY=value pairs
+fn read_vorbis_comments(bytes: &[u8], info: &mut AudioInfo) -> Option<()> {
+    let mut reader = ByteReader::new(bytes);
+
+    let vendor_length = reader.u32_le()? as usize;
+    reader.take(vendor_length)?;
+
+    let count = reader.u32_le()?;
+
+    for _ in 0..count {
+        let length = reader.u32_le()? as usize;
+        let comment = String::from_utf8_lossy(reader.take(length)?).into_owned();
+
+        let mut parts = comment.splitn(2, '=');
+        let key = parts.next()?.to_uppercase();
+
+        let value = match parts.next() {
+            Some(value) => value.to_owned(),
+            None => continue
+        };
+
+        match key.as_str() {
+            "TITLE" => set_text(&mut info.title, Some(value)),
+            "ARTIST" => set_text(&mut info.artist, Some(value)),
+            "ALBUM" => set_text(&mut info.album, Some(value)),
+            "GENRE" => set_text(&mut info.genre, Some(value)),
+            "ISRC" => set_text(&mut info.isrc, Some(value)),
+            "TRACKNUMBER" => info.track_number = info.track_number.or(parse_number(&value)),
+            "DATE" | "YEAR" => info.year = info.year.or(parse_number(&value)),
+            // a base64 FLAC picture block
+            "METADATA_BLOCK_PICTURE" => info.set_cover(base64::decode(value.trim())
+                .ok()
+                .and_then(|block| read_flac_picture(&block))),
+            _ => {}
+        };
+    }
+
+    Some(())
+}
+
+fn read_flac_streaminfo(block: &[u8], info: &mut AudioInfo) -> Option<()> {
+    // 20 bits of sample rate, 3 of channels - 1, 5 of bits per sample - 1 and 36 of samples
+    let bytes = block.get(10..18)?;
+
+    let sample_rate = (bytes[0] as u32) << 12 | (bytes[1] as u32) << 4 | (bytes[2] as u32) >> 4;
+    let channels = ((bytes[2] >> 1) & 7) as i32 + 1;
+    let samples = ((bytes[3] & 0x0F) as u64) << 32 | u32_be(&bytes[4..8]) as u64;
+
+    info.channels = Some(channels);
+
+    if sample_rate > 0 {
+        info.sample_rate = Some(sample_rate as i32);
+
+        // 0 means the encoder didn't know
+        if samples > 0 {
+            info.duration = Some(samples as f64 / sample_rate as f64);
+        }
+    }
+
+    Some(())
+}
+
+fn read_flac(file: &mut File, length: u64, info: &mut AudioInfo) -> Result<(), ::failure::Error> {
+    // some taggers put an ID3v2 tag in front anyway
+    let mut position = read_id3v2(file, info)?;
+
+    if read_at(file, position, 4)? != b"fLaC" {
+        return Err(format_err!("Not a FLAC stream"));
+    }
+
+    position += 4;
+
+    loop {
+        let header = read_at(file, position, 4)?;
+
+        if header.len() < 4 {
+            break;
+        }
+
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let block_length = (u32_be(&header) & 0x00FF_FFFF) as u64;
+
+        position += 4;
+
+        if block_length <= MAX_TAG_SIZE {
+            match block_type {
+                0 => { read_flac_streaminfo(&read_at(file, position, block_length)?, info); },
+                4 => { read_vorbis_comments(&read_at(file, position, block_length)?, info); },
+                6 => info.set_cover(read_flac_picture(&read_at(file, position, block_length)?)),
+                _ => {}
+            };
+        }
+
+        position += block_length;
+
+        if last {
+            break;
+        }
+    }
+
+    info.set_average_bitrate(length.saturating_sub(position));
+
+    Ok(())
+}
+
+// the first two packets of the first logical stream, which are the identification
+// and comment headers for both Vorbis and Opus
+fn read_ogg_headers(file: &mut File, length: u64) -> io::Result<Option<(u32, Vec<Vec<u8>>)>> {
+    let mut offset = 0;
+    let mut serial = None;
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+
+    while packets.len() < 2 && offset < length && offset < MAX_TAG_SIZE {
+        let header = read_at(file, offset, 27)?;
+
+        if header.len() < 27 || &header[..4] != b"OggS" {
+            return Ok(None);
+        }
+
+        let page_serial = u32_le(&header[14..18]);
+        let segments = header[26] as u64;
+
+        let lacing = read_at(file, offset + 27, segments)?;
+        let body_length: u64 = lacing.iter().map(|&lace| lace as u64).sum();
+        let body = read_at(file, offset + 27 + segments, body_length)?;
+
+        offset += 27 + segments + body_length;
+
+        if *serial.get_or_insert(page_serial) != page_serial {
+            continue;
+        }
+
+        // a packet continues into the next segment as long as its segments are 255 bytes
+        let mut start = 0;
+
+        for &lace in lacing.iter() {
+            let end = cmp::min(start + lace as usize, body.len());
+            packet.extend_from_slice(&body[start..end]);
+            start = end;
+
+            if lace < 255 {
+                packets.push(mem::replace(&mut packet, Vec::new()));
+
+                if packets.len() == 2 {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(match serial {
+        Some(serial) if packets.len() == 2 => Some((serial, packets)),
+        _ => None
+    })
+}
+
+// the granule position of a stream's last page is its length in samples
+fn read_ogg_granule(file: &mut File, length: u64, serial: u32) -> io::Result<Option<u64>> {
+    let start = length.saturating_sub(OGG_TAIL_SIZE);
+    let tail = read_at(file, start, OGG_TAIL_SIZE)?;
+
+    let granule = (0..tail.len().saturating_sub(26))
+        .rev()
+        .filter(|&offset| &tail[offset..offset + 4] == b"OggS")
+        .filter(|&offset| u32_le(&tail[offset + 14..offset + 18]) == serial)
+        .map(|offset| u64_le(&tail[offset + 6..offset + 14]))
+        // pages where no packet ends have no granule position
+        .find(|&granule| granule != u64::max_value());
+
+    Ok(granule)
+}
+
+fn read_ogg(file: &mut File, length: u64, info: &mut AudioInfo) -> Result<(), ::failure::Error> {
+    let (serial, packets) = read_ogg_headers(file, length)?
+        .ok_or(format_err!("No Ogg headers found"))?;
+
+    let granule = read_ogg_granule(file, length, serial)?;
+
+    let identification = &packets[0];
+    let comments = &packets[1];
+
+    if identification.starts_with(b"\x01vorbis") && identification.len() >= 24 {
+        let sample_rate = u32_le(&identification[12..16]);
+        let nominal_bitrate = u32_le(&identification[20..24]) as i32;
+
+        info.channels = Some(identification[11] as i32);
+
+        if nominal_bitrate > 0 {
+            info.bitrate = Some(nominal_bitrate);
+        }
+
+        if comments.starts_with(b"\x03vorbis") {
+            read_vorbis_comments(&comments[7..], info);
+        }
+
+        if sample_rate > 0 {
+            info.sample_rate = Some(sample_rate as i32);
+            info.duration = granule.map(|granule| granule as f64 / sample_rate as f64);
+        }
+    } else if identification.starts_with(b"OpusHead") && identification.len() >= 19 {
+        let pre_skip = (identification[11] as u64) << 8 | identification[10] as u64;
+
+        info.channels = Some(identification[9] as i32);
+        // Opus always decodes at 48kHz, whatever the input was
+        info.sample_rate = Some(48000);
+
+        if comments.starts_with(b"OpusTags") {
+            read_vorbis_comments(&comments[8..], info);
+        }
+
+        if let Some(granule) = granule {
+            info.duration = Some(granule.saturating_sub(pre_skip) as f64 / 48000.0);
+        }
+    } else {
+        return Err(format_err!("Ogg stream isn't Vorbis or Opus"));
+    }
+
+    info.set_average_bitrate(length);
+
+    Ok(())
+}
+
+// None for files that aren't MP3, FLAC, Vorbis or Opus
+pub fn read(path: &Path, media_type: &str) -> Result<Option<AudioInfo>, ::failure::Error> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+
+    let mut info = AudioInfo::default();
+
+    match media_type {
+        "audio/mpeg" | "audio/mp3" => read_mp3(&mut file, length, &mut info)?,
+        "audio/flac" | "audio/x-flac" => read_flac(&mut file, length, &mut info)?,
+        "audio/ogg" | "audio/opus" | "audio/vorbis" => read_ogg(&mut file, length, &mut info)?,
+        _ => return Ok(None)
+    };
+
+    Ok(Some(info))
+}
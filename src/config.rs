@@ -1,36 +1,142 @@
-use ::url::{self, Url};
+use ::url::Url;
+
+const DEFAULT_DELIVERY_MAX_AGE: i32 = 3 * 24 * 60 * 60;
+const DEFAULT_PROCESSED_RETENTION: i32 = 14 * 24 * 60 * 60;
+const DEFAULT_ACTOR_TTL: i32 = 24 * 60 * 60;
+const DEFAULT_SIGNATURE_MAX_SKEW: i32 = 60 * 60;
+const DEFAULT_OUTBOX_PAGE_SIZE: i64 = 20;
+const DEFAULT_TEMPLATE_DIR: &str = "templates";
+const DEFAULT_S3_REGION: &str = "us-east-1";
 
 #[derive(Debug)]
 pub struct RawConfig {
     pub db_url: String,
     pub pub_key: String,
+    pub priv_key: String,
     pub root_url: String,
     pub actor_username: String,
     pub actor_name: String,
-    pub media_dir: String
+    pub actor_bio: Option<String>,
+    pub media_dir: String,
+    pub template_dir: Option<String>,
+    pub admin_password_hash: Option<String>,
+    pub delivery_max_age: Option<String>,
+    pub processed_retention: Option<String>,
+    pub actor_ttl: Option<String>,
+    pub signature_max_skew: Option<String>,
+    pub hide_followers: Option<String>,
+    pub outbox_page_size: Option<String>,
+    pub media_store: Option<String>,
+    pub s3_endpoint: Option<String>,
+    pub s3_region: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    pub s3_redirect: Option<String>
+}
+
+fn required(value: Option<String>, name: &str) -> Result<String, ::failure::Error> {
+    value.ok_or(format_err!("{} must be set to use the S3 media store", name))
 }
 
 impl RawConfig {
-    pub fn validate(self) -> Result<Config, url::ParseError> {
+    pub fn validate(self) -> Result<Config, ::failure::Error> {
         Ok(Config {
             db_url: self.db_url,
             pub_key: self.pub_key,
+            priv_key: self.priv_key,
             root_url: Url::parse(&self.root_url)?,
             actor_username: self.actor_username,
             actor_name: self.actor_name,
-            media_dir: self.media_dir
+            actor_bio: self.actor_bio,
+            media_dir: self.media_dir,
+            template_dir: self.template_dir
+                .unwrap_or(DEFAULT_TEMPLATE_DIR.to_owned()),
+            admin_password_hash: self.admin_password_hash,
+            delivery_max_age: match self.delivery_max_age {
+                Some(text) => text.parse()?,
+                None => DEFAULT_DELIVERY_MAX_AGE
+            },
+            processed_retention: match self.processed_retention {
+                Some(text) => text.parse()?,
+                None => DEFAULT_PROCESSED_RETENTION
+            },
+            actor_ttl: match self.actor_ttl {
+                Some(text) => text.parse()?,
+                None => DEFAULT_ACTOR_TTL
+            },
+            signature_max_skew: match self.signature_max_skew {
+                Some(text) => text.parse()?,
+                None => DEFAULT_SIGNATURE_MAX_SKEW
+            },
+            hide_followers: match self.hide_followers {
+                Some(text) => text.parse()?,
+                None => false
+            },
+            outbox_page_size: match self.outbox_page_size {
+                Some(text) => match text.parse()? {
+                    size if size > 0 => size,
+                    _ => return Err(format_err!("Outbox page size must be positive"))
+                },
+                None => DEFAULT_OUTBOX_PAGE_SIZE
+            },
+            store: match self.media_store.as_ref().map(String::as_str) {
+                None | Some("fs") => StoreConfig::Fs,
+                Some("s3") => StoreConfig::S3(S3Config {
+                    endpoint: Url::parse(&required(self.s3_endpoint, "JADWIGA_S3_ENDPOINT")?)?,
+                    region: self.s3_region
+                        .unwrap_or(DEFAULT_S3_REGION.to_owned()),
+                    bucket: required(self.s3_bucket, "JADWIGA_S3_BUCKET")?,
+                    access_key: required(self.s3_access_key, "JADWIGA_S3_ACCESS_KEY")?,
+                    secret_key: required(self.s3_secret_key, "JADWIGA_S3_SECRET_KEY")?,
+                    redirect: match self.s3_redirect {
+                        Some(text) => text.parse()?,
+                        None => false
+                    }
+                }),
+                Some(other) => return Err(format_err!("Unknown media store {}", other))
+            }
         })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub db_url: String,
     pub pub_key: String,
+    pub priv_key: String,
     pub root_url: Url,
     pub actor_username: String,
     pub actor_name: String,
-    pub media_dir: String
+    pub actor_bio: Option<String>,
+    pub media_dir: String,
+    pub template_dir: String,
+    pub admin_password_hash: Option<String>,
+    pub delivery_max_age: i32,
+    pub processed_retention: i32,
+    pub actor_ttl: i32,
+    pub signature_max_skew: i32,
+    pub hide_followers: bool,
+    pub outbox_page_size: i64,
+    pub store: StoreConfig
+}
+
+// S3 or anything that speaks its API, like MinIO. Objects are addressed path-style,
+// <endpoint>/<bucket>/<key>, which every implementation supports
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub endpoint: Url,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub redirect: bool
+}
+
+#[derive(Debug, Clone)]
+pub enum StoreConfig {
+    Fs,
+    S3(S3Config)
 }
 
 impl Config {
@@ -38,6 +144,10 @@ impl Config {
         self.root_url.as_str().to_owned()
     }
 
+    pub fn key_id(&self) -> String {
+        format!("{}#main-key", self.actor_url())
+    }
+
     pub fn inbox_url(&self) -> String {
         self.root_url.join("/_inbox").unwrap().as_str().to_owned()
     }
@@ -46,6 +156,14 @@ impl Config {
         self.root_url.join("/_outbox").unwrap().as_str().to_owned()
     }
 
+    pub fn followers_url(&self) -> String {
+        self.root_url.join("/_followers").unwrap().as_str().to_owned()
+    }
+
+    pub fn following_url(&self) -> String {
+        self.root_url.join("/_following").unwrap().as_str().to_owned()
+    }
+
     pub fn post_url(&self, uri_name: &str) -> String {
         self.root_url.join(&format!("/{}", uri_name)).unwrap().as_str().to_owned()
     }
@@ -54,7 +172,19 @@ impl Config {
         self.root_url.join(&format!("/_activity/{}", uri_name)).unwrap().as_str().to_owned()
     }
 
+    pub fn static_url(&self, file: &str) -> String {
+        self.root_url.join(&format!("/_static/{}", file)).unwrap().as_str().to_owned()
+    }
+
     pub fn media_url(&self, file: &str) -> String {
         self.root_url.join(&format!("/_media/{}", file)).unwrap().as_str().to_owned()
     }
+
+    pub fn peaks_url(&self, hash: &str) -> String {
+        self.media_url(&format!("{}/peaks.json", hash))
+    }
+
+    pub fn waveform_url(&self, hash: &str) -> String {
+        self.media_url(&format!("{}/waveform.png", hash))
+    }
 }
@@ -0,0 +1,198 @@
+use ::std::cmp;
+use ::std::collections::BTreeSet;
+use ::std::thread;
+use ::std::time::Duration;
+
+use ::chrono::offset::Utc;
+use ::diesel::prelude::*;
+use ::diesel::sqlite::SqliteConnection;
+use ::reqwest::{header, mime, StatusCode};
+use ::serde_json::{self, Value};
+use ::url::Url;
+
+use config::Config;
+use db::SqlitePool;
+use models;
+use remote;
+use schema;
+use sign;
+
+const POLL_INTERVAL: u64 = 5;
+const BATCH_SIZE: i64 = 20;
+const REQUEST_TIMEOUT: u64 = 30;
+const BACKOFF_BASE: i32 = 60;
+const BACKOFF_MAX: i32 = 6 * 60 * 60;
+
+#[derive(Debug)]
+pub enum DeliveryError {
+    Temporary(String),
+    // the inbox turned the activity down, which may only be until it has our key; retried
+    // like a temporary error
+    Refused(String),
+    Permanent(String)
+}
+
+fn now() -> i32 {
+    Utc::now().timestamp() as i32
+}
+
+fn backoff(attempts: i32) -> i32 {
+    let exponent = cmp::min(cmp::max(attempts - 1, 0), 16) as u32;
+
+    cmp::min(BACKOFF_BASE * 2i32.pow(exponent), BACKOFF_MAX)
+}
+
+pub fn deliver(config: &Config, inbox: &str, activity: &Value, rfc9421: bool) -> Result<(), DeliveryError> {
+    let inbox_url = Url::parse(inbox)
+        .map_err(|e| DeliveryError::Permanent(format!("Invalid inbox URL {}: {:?}", inbox, e)))?;
+
+    let body = serde_json::to_string(activity)
+        .map_err(|e| DeliveryError::Permanent(format!("Failed to serialize activity: {:?}", e)))?;
+
+    let signed_headers = if rfc9421 {
+        sign::sign_request_rfc9421(config, "POST", &inbox_url, Some(body.as_bytes()))
+    } else {
+        sign::sign_request(config, "POST", &inbox_url, Some(body.as_bytes()))
+    }.map_err(|e| DeliveryError::Temporary(format!("Failed to sign request: {:?}", e)))?;
+
+    let activity_json = "application/activity+json".parse::<mime::Mime>()
+        .map_err(|e| DeliveryError::Permanent(format!("{:?}", e)))?;
+
+    let client = ::reqwest::Client::builder()
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+        .build()
+        .map_err(|e| DeliveryError::Temporary(format!("Failed to create client: {:?}", e)))?;
+
+    let response = client
+        .post(inbox)
+        .headers(signed_headers)
+        .header(header::ContentType(activity_json))
+        .body(body)
+        .send()
+        .map_err(|e| DeliveryError::Temporary(format!("Failed to deliver to {}: {:?}", inbox, e)))?;
+
+    let status = response.status();
+
+    if status.is_success() {
+        Ok(())
+    } else if status.is_server_error() || status == StatusCode::TooManyRequests {
+        Err(DeliveryError::Temporary(format!("Delivery to {} failed: {}", inbox, status)))
+    } else {
+        Err(DeliveryError::Refused(format!("Delivery to {} failed: {}", inbox, status)))
+    }
+}
+
+pub fn enqueue(conn: &SqliteConnection, inbox: &str, activity: &Value) -> Result<(), ::failure::Error> {
+    let new_delivery = models::NewDelivery {
+        inbox: inbox.to_owned(),
+        json: serde_json::to_string(activity)?
+    };
+
+    ::diesel::insert_into(schema::deliveries::table)
+        .values(&new_delivery)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn enqueue_to_followers(conn: &SqliteConnection, activity: &Value) -> Result<(), ::failure::Error> {
+    let followers = schema::followers::table
+        .load::<models::Follower>(conn)?;
+
+    let inboxes: BTreeSet<String> = followers.into_iter()
+        .map(|follower| follower.shared_inbox.unwrap_or(follower.inbox))
+        .collect();
+
+    conn.transaction::<_, ::failure::Error, _>(|| {
+        for inbox in inboxes.iter() {
+            enqueue(conn, inbox, activity)?;
+        }
+
+        Ok(())
+    })
+}
+
+fn attempt(config: &Config, conn: &SqliteConnection, delivery: &models::Delivery) -> Result<(), ::failure::Error> {
+    let delivery_q = schema::deliveries::table.filter(
+        schema::deliveries::id.eq(delivery.id)
+    );
+
+    let rfc9421 = remote::inbox_supports_rfc9421(conn, &delivery.inbox)
+        .unwrap_or(false);
+
+    let result = serde_json::from_str::<Value>(&delivery.json)
+        .map_err(|e| DeliveryError::Permanent(format!("Failed to parse activity: {:?}", e)))
+        .and_then(|activity| {
+            match deliver(config, &delivery.inbox, &activity, rfc9421) {
+                // Fall back to draft-cavage if the peer rejected the RFC 9421 signature
+                Err(DeliveryError::Refused(_)) if rfc9421 =>
+                    deliver(config, &delivery.inbox, &activity, false),
+                result =>
+                    result
+            }
+        });
+
+    match result {
+        Ok(()) => {
+            println!("deliver(id = {}) succeeded", delivery.id);
+
+            ::diesel::delete(delivery_q).execute(conn)?;
+        },
+        Err(DeliveryError::Permanent(why)) => {
+            println!("deliver(id = {}) failed permanently: {}", delivery.id, why);
+
+            ::diesel::delete(delivery_q).execute(conn)?;
+        },
+        Err(DeliveryError::Temporary(why)) | Err(DeliveryError::Refused(why)) => {
+            let now = now();
+
+            if now - delivery.datetime > config.delivery_max_age {
+                println!("deliver(id = {}) failed, giving up: {}", delivery.id, why);
+
+                ::diesel::delete(delivery_q).execute(conn)?;
+            } else {
+                let attempts = delivery.attempts + 1;
+
+                println!("deliver(id = {}) failed, attempt {}: {}", delivery.id, attempts, why);
+
+                ::diesel::update(delivery_q)
+                    .set((
+                        schema::deliveries::attempts.eq(attempts),
+                        schema::deliveries::next_attempt.eq(now + backoff(attempts)),
+                        schema::deliveries::last_error.eq(Some(why))
+                    ))
+                    .execute(conn)?;
+            }
+        }
+    };
+
+    Ok(())
+}
+
+fn run_due(config: &Config, pool: &SqlitePool) -> Result<(), ::failure::Error> {
+    let conn = pool.get()?;
+
+    let due = schema::deliveries::table
+        .filter(schema::deliveries::next_attempt.le(now()))
+        .order(schema::deliveries::next_attempt.asc())
+        .limit(BATCH_SIZE)
+        .load::<models::Delivery>(&conn)?;
+
+    for delivery in due.iter() {
+        attempt(config, &conn, delivery)?;
+    }
+
+    Ok(())
+}
+
+pub fn spawn_worker(config: Config, pool: SqlitePool) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        loop {
+            if let Err(e) = run_due(&config, &pool) {
+                println!("Delivery worker failed: {:?}", e);
+            }
+
+            thread::sleep(Duration::from_secs(POLL_INTERVAL));
+        }
+    })
+}
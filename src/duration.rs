@@ -0,0 +1,260 @@
+use ::std::fs::File;
+use ::std::io::{self, Read, Seek, SeekFrom};
+use ::std::path::Path;
+
+// Durations of uploads audio::read doesn't cover, from their container headers:
+//
+// MP4, M4A, MOV:    the movie header (moov/mvhd) timescale and duration
+// WAV:              the data chunk size over the fmt chunk's byte rate
+// WebM, Matroska:   Segment/Info Duration, scaled by its TimecodeScale
+//
+// Returns None when the container doesn't say.
+
+const MATROSKA_EBML: u64 = 0x1A45DFA3;
+const MATROSKA_SEGMENT: u64 = 0x18538067;
+const MATROSKA_INFO: u64 = 0x1549A966;
+const MATROSKA_TIMECODE_SCALE: u64 = 0x2AD7B1;
+const MATROSKA_DURATION: u64 = 0x4489;
+const MATROSKA_CLUSTER: u64 = 0x1F43B675;
+const DEFAULT_TIMECODE_SCALE: u64 = 1000000;
+
+fn read_bytes<R: Read>(reader: &mut R, length: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn uint_be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn uint_le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+// Leaves the file at the start of the box's contents and returns where they end
+fn find_mp4_box(file: &mut File, end: u64, name: &[u8]) -> io::Result<Option<u64>> {
+    let mut position = file.seek(SeekFrom::Current(0))?;
+
+    while position + 8 <= end {
+        let header = read_bytes(file, 8)?;
+        let mut size = uint_be(&header[0..4]);
+        let mut header_size = 8;
+
+        if size == 1 {
+            size = uint_be(&read_bytes(file, 8)?);
+            header_size = 16;
+        } else if size == 0 {
+            // runs to the end of the file
+            size = end - position;
+        }
+
+        if size < header_size || position + size > end {
+            return Ok(None);
+        }
+
+        if &header[4..8] == name {
+            return Ok(Some(position + size));
+        }
+
+        position = file.seek(SeekFrom::Start(position + size))?;
+    }
+
+    Ok(None)
+}
+
+fn read_mp4(file: &mut File, length: u64) -> io::Result<Option<f64>> {
+    let moov_end = match find_mp4_box(file, length, b"moov")? {
+        Some(end) => end,
+        None => return Ok(None)
+    };
+
+    if find_mp4_box(file, moov_end, b"mvhd")?.is_none() {
+        return Ok(None);
+    }
+
+    let version = read_bytes(file, 4)?[0];
+
+    // creation and modification times come first, then the timescale and duration
+    let (timescale, duration, unknown) = if version == 1 {
+        let header = read_bytes(file, 28)?;
+        (uint_be(&header[16..20]), uint_be(&header[20..28]), u64::max_value())
+    } else {
+        let header = read_bytes(file, 16)?;
+        (uint_be(&header[8..12]), uint_be(&header[12..16]), u32::max_value() as u64)
+    };
+
+    if timescale == 0 || duration == unknown {
+        return Ok(None);
+    }
+
+    Ok(Some(duration as f64 / timescale as f64))
+}
+
+fn read_wav(file: &mut File, length: u64) -> io::Result<Option<f64>> {
+    let header = read_bytes(file, 12)?;
+
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(None);
+    }
+
+    let mut position = 12;
+    let mut byte_rate = None;
+
+    while position + 8 <= length {
+        let chunk = read_bytes(file, 8)?;
+        let size = uint_le(&chunk[4..8]);
+
+        match &chunk[0..4] {
+            b"fmt " if size >= 12 => {
+                byte_rate = Some(uint_le(&read_bytes(file, 12)?[8..12]));
+            },
+            // the data chunk of a WAV still being written can claim more than is there
+            b"data" => return Ok(match byte_rate {
+                Some(byte_rate) if byte_rate > 0 => {
+                    let size = if size > length - position - 8 { length - position - 8 } else { size };
+                    Some(size as f64 / byte_rate as f64)
+                },
+                _ => None
+            }),
+            _ => {}
+        };
+
+        // chunks are padded to an even size
+        position += 8 + size + (size & 1);
+        file.seek(SeekFrom::Start(position))?;
+    }
+
+    Ok(None)
+}
+
+// EBML variable length integers: the number of leading zeros in the first byte gives
+// the length. IDs keep the length marker bit, sizes don't.
+fn read_vint(file: &mut File, keep_marker: bool) -> io::Result<Option<(u64, bool)>> {
+    let first = read_bytes(file, 1)?[0];
+
+    if first == 0 {
+        return Ok(None);
+    }
+
+    let length = first.leading_zeros() as usize + 1;
+    let first_value = if keep_marker { first } else { first & (0xFFu16 >> length) as u8 };
+
+    let rest = read_bytes(file, length - 1)?;
+    let value = rest.iter().fold(first_value as u64, |value, &byte| (value << 8) | byte as u64);
+
+    let unknown = !keep_marker && value == (1u64 << (7 * length)) - 1;
+
+    Ok(Some((value, unknown)))
+}
+
+// The ID of the element at the file's position, and where it ends
+fn read_element(file: &mut File, end: u64) -> io::Result<Option<(u64, u64)>> {
+    let id = match read_vint(file, true)? {
+        Some((id, _)) => id,
+        None => return Ok(None)
+    };
+
+    let (size, unknown) = match read_vint(file, false)? {
+        Some(size) => size,
+        None => return Ok(None)
+    };
+
+    let position = file.seek(SeekFrom::Current(0))?;
+
+    // an unknown size runs to the end of whatever contains the element
+    if unknown {
+        Ok(Some((id, end)))
+    } else {
+        Ok(Some((id, position + size)))
+    }
+}
+
+fn find_element(file: &mut File, end: u64, wanted: u64) -> io::Result<Option<u64>> {
+    let mut position = file.seek(SeekFrom::Current(0))?;
+
+    while position < end {
+        let (id, element_end) = match read_element(file, end)? {
+            Some(element) => element,
+            None => return Ok(None)
+        };
+
+        if id == wanted {
+            return Ok(Some(element_end));
+        }
+
+        // Info comes before the media itself
+        if id == MATROSKA_CLUSTER || element_end > end {
+            return Ok(None);
+        }
+
+        position = file.seek(SeekFrom::Start(element_end))?;
+    }
+
+    Ok(None)
+}
+
+fn read_matroska(file: &mut File, length: u64) -> io::Result<Option<f64>> {
+    match read_element(file, length)? {
+        Some((MATROSKA_EBML, header_end)) => file.seek(SeekFrom::Start(header_end))?,
+        _ => return Ok(None)
+    };
+
+    let segment_end = match find_element(file, length, MATROSKA_SEGMENT)? {
+        Some(end) => end,
+        None => return Ok(None)
+    };
+
+    let info_end = match find_element(file, segment_end, MATROSKA_INFO)? {
+        Some(end) => end,
+        None => return Ok(None)
+    };
+
+    let mut timecode_scale = DEFAULT_TIMECODE_SCALE;
+    let mut duration = None;
+    let mut position = file.seek(SeekFrom::Current(0))?;
+
+    while position < info_end {
+        let (id, element_end) = match read_element(file, info_end)? {
+            Some(element) => element,
+            None => break
+        };
+
+        let size = element_end.saturating_sub(file.seek(SeekFrom::Current(0))?);
+
+        match (id, size) {
+            (MATROSKA_TIMECODE_SCALE, 1...8) => {
+                timecode_scale = uint_be(&read_bytes(file, size as usize)?);
+            },
+            (MATROSKA_DURATION, 4) => {
+                duration = Some(f32::from_bits(uint_be(&read_bytes(file, 4)?) as u32) as f64);
+            },
+            (MATROSKA_DURATION, 8) => {
+                duration = Some(f64::from_bits(uint_be(&read_bytes(file, 8)?)));
+            },
+            _ => {}
+        };
+
+        position = file.seek(SeekFrom::Start(element_end))?;
+    }
+
+    // Duration is in TimecodeScale units, which are nanoseconds
+    Ok(duration
+        .filter(|duration| duration.is_finite() && *duration > 0.0)
+        .map(|duration| duration * timecode_scale as f64 / 1e9))
+}
+
+// seconds
+pub fn read(path: &Path, media_type: &str) -> Result<Option<f64>, ::failure::Error> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+
+    let duration = match media_type {
+        "video/mp4" | "audio/mp4" | "audio/x-m4a" | "video/quicktime" => read_mp4(&mut file, length)?,
+        "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => read_wav(&mut file, length)?,
+        "video/webm" | "audio/webm" | "video/x-matroska" | "audio/x-matroska" => read_matroska(&mut file, length)?,
+        _ => None
+    };
+
+    Ok(duration)
+}
@@ -0,0 +1,504 @@
+use ::std::collections::VecDeque;
+use ::std::sync::{Arc, Condvar, Mutex};
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::thread;
+use ::std::time::Duration;
+
+use ::chrono::offset::Utc;
+use ::diesel::dsl::exists;
+use ::diesel::prelude::*;
+use ::diesel::sqlite::SqliteConnection;
+use ::serde_json::{self, Value};
+
+use config::Config;
+use db::SqlitePool;
+use delivery;
+use models;
+use ns;
+use remote;
+use schema;
+
+const POLL_INTERVAL: u64 = 30;
+const BATCH_SIZE: i64 = 20;
+const MAX_OUTCOMES: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityOutcome {
+    pub rowid: i32,
+    pub id: String,
+    pub error: Option<String>,
+    pub datetime: i64
+}
+
+#[derive(Debug)]
+struct WorkerState {
+    paused: AtomicBool,
+    pending: Mutex<bool>,
+    wakeup: Condvar,
+    outcomes: Mutex<VecDeque<ActivityOutcome>>
+}
+
+#[derive(Debug, Clone)]
+pub struct InboxWorker {
+    state: Arc<WorkerState>
+}
+
+impl InboxWorker {
+    pub fn notify(&self) {
+        let mut pending = self.state.pending.lock().unwrap();
+        *pending = true;
+        self.state.wakeup.notify_one();
+    }
+
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+        self.notify();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn outcomes(&self) -> Vec<ActivityOutcome> {
+        self.state.outcomes.lock().unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    fn record(&self, outcome: ActivityOutcome) {
+        let mut outcomes = self.state.outcomes.lock().unwrap();
+
+        if outcomes.len() >= MAX_OUTCOMES {
+            outcomes.pop_back();
+        }
+
+        outcomes.push_front(outcome);
+    }
+
+    fn wait(&self) {
+        let mut pending = self.state.pending.lock().unwrap();
+
+        if !*pending {
+            pending = self.state.wakeup
+                .wait_timeout(pending, Duration::from_secs(POLL_INTERVAL))
+                .unwrap()
+                .0;
+        }
+
+        *pending = false;
+    }
+}
+
+pub fn actor_id(activity: &Value) -> Result<&str, ::failure::Error> {
+    let actor = activity.get("actor")
+        .ok_or(format_err!("No 'actor' field found"))?;
+
+    let id = match actor {
+        Value::String(s) => Some(s.as_str()),
+        Value::Object(_) => actor.get("id").and_then(Value::as_str),
+        _ => None
+    };
+
+    id.ok_or(format_err!("Invalid 'actor' field"))
+}
+
+fn get_accept(follower: &models::Follower, follow: &Value, config: &Config) -> Value {
+    let actor_url = config.actor_url();
+
+    json!({
+        "@context": ns::ACTIVITYSTREAMS,
+
+        "type": "Accept",
+        "id": format!("{}#accepts/follows/{}", actor_url, follower.id),
+        "actor": actor_url,
+        "object": follow
+    })
+}
+
+fn handle_follow(config: &Config, conn: &SqliteConnection, json: &Value, actor: &Value, object: &Value) -> Result<(), ::failure::Error> {
+    let object_str = object.as_str()
+        .ok_or(format_err!("Invalid non-string 'object' field"))?;
+
+    if object_str != config.actor_url() {
+        return Err(format_err!("Object is a different actor"));
+    }
+
+    let follow_id = json.get("id")
+        .ok_or(format_err!("No 'id' field found"))?
+        .as_str()
+        .ok_or(format_err!("Invalid non-string 'id' field"))?;
+
+    let actor_str = actor.as_str()
+        .ok_or(format_err!("Invalid non-string 'actor' field"))?;
+
+    let remote_actor = remote::get_actor(config, conn, actor_str, false)?;
+
+    let new_follower = models::NewFollower {
+        actor: remote_actor.id,
+        inbox: remote_actor.inbox,
+        shared_inbox: remote_actor.shared_inbox,
+        follow_id: follow_id.to_owned()
+    };
+
+    // a re-follow updates the existing row, so the follower keeps its id
+    conn.transaction::<_, ::failure::Error, _>(|| {
+        let follower_q = schema::followers::table.filter(
+            schema::followers::actor.eq(&new_follower.actor)
+        );
+
+        let updated = ::diesel::update(follower_q)
+            .set((
+                schema::followers::inbox.eq(&new_follower.inbox),
+                schema::followers::shared_inbox.eq(&new_follower.shared_inbox),
+                schema::followers::follow_id.eq(&new_follower.follow_id)
+            ))
+            .execute(conn)?;
+
+        if updated == 0 {
+            ::diesel::insert_into(schema::followers::table)
+                .values(&new_follower)
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })?;
+
+    let follower = schema::followers::table
+        .filter(schema::followers::actor.eq(&new_follower.actor))
+        .first::<models::Follower>(conn)?;
+
+    let accept = get_accept(&follower, json, config);
+
+    delivery::enqueue(conn, &follower.inbox, &accept)?;
+
+    Ok(())
+}
+
+fn object_id(object: &Value) -> Option<&str> {
+    match object {
+        Value::String(s) => Some(s.as_str()),
+        Value::Object(_) => object.get("id").and_then(Value::as_str),
+        _ => None
+    }
+}
+
+fn object_type(object: &Value) -> Option<String> {
+    object.get("type")
+        .and_then(Value::as_str)
+        .map(|typ| typ.to_lowercase())
+}
+
+// Undo{Follow} from the follower. Anything else being undone, such as a Like, is of no
+// interest, and neither is a Follow that can't be matched up with a follower.
+fn handle_undo(conn: &SqliteConnection, actor_str: &str, object: &Value) -> Result<(), ::failure::Error> {
+    match object_type(object) {
+        Some(ref typ) if typ != "follow" => {
+            println!("Ignoring Undo of {}", typ);
+            return Ok(());
+        },
+        _ => {}
+    };
+
+    let follow_id = match object_id(object) {
+        Some(follow_id) => follow_id,
+        None => {
+            println!("Ignoring Undo of a Follow without an id");
+            return Ok(());
+        }
+    };
+
+    // an embedded Follow has to be the undoer's own
+    if object.get("actor").is_some() {
+        match actor_id(object) {
+            Ok(follow_actor) if follow_actor == actor_str => {},
+            _ => {
+                println!("Ignoring Undo of {} by someone else", follow_id);
+                return Ok(());
+            }
+        };
+    }
+
+    // Only the actor of the relationship may end it, so an unknown or foreign follow is a no-op
+    let follower_q = schema::followers::table
+        .filter(schema::followers::actor.eq(actor_str))
+        .filter(schema::followers::follow_id.eq(follow_id));
+
+    let deleted = ::diesel::delete(follower_q).execute(conn)?;
+
+    println!("Removed {} follower(s) for follow {}", deleted, follow_id);
+
+    Ok(())
+}
+
+fn handle_delete(conn: &SqliteConnection, actor_str: &str, object: &Value) -> Result<(), ::failure::Error> {
+    let deleted_id = object_id(object)
+        .ok_or(format_err!("Invalid 'object' field"))?;
+
+    if deleted_id != actor_str {
+        println!("Ignoring Delete of {}", deleted_id);
+        return Ok(());
+    }
+
+    let follower_q = schema::followers::table.filter(
+        schema::followers::actor.eq(actor_str)
+    );
+
+    ::diesel::delete(follower_q).execute(conn)?;
+
+    let cached_q = schema::remote_actors::table.filter(
+        schema::remote_actors::id.eq(actor_str)
+    );
+
+    ::diesel::delete(cached_q).execute(conn)?;
+
+    Ok(())
+}
+
+fn handle_activity(config: &Config, conn: &SqliteConnection, activity: &models::Activity) -> Result<(), ::failure::Error> {
+    let json: Value = serde_json::from_str(&activity.json)?;
+    
+    let typ: String = json.get("type")
+        .ok_or(format_err!("No 'type' field found"))?
+        .as_str()
+        .ok_or(format_err!("Invalid non-string 'type' field"))?
+        .to_lowercase();
+
+    let actor: &Value = json.get("actor")
+        .ok_or(format_err!("No 'actor' field found"))?;
+
+    let object = json.get("object")
+        .ok_or(format_err!("No 'object' field found"))?;
+
+    let actor_str = actor_id(&json)?;
+
+    match &typ as &str {
+        "follow" => {
+            println!("follow!");
+
+            handle_follow(config, conn, &json, actor, object)?;
+        },
+        "undo" => {
+            println!("undo!");
+
+            handle_undo(conn, actor_str, object)?;
+        },
+        "reject" => {
+            // A Reject answers a Follow sent by us, not one of our followers, and jadwiga
+            // doesn't follow anyone, so there's nothing to undo
+            println!("Ignoring Reject of {}", object_id(object).unwrap_or("an object without an id"));
+        },
+        "delete" => {
+            println!("delete!");
+
+            handle_delete(conn, actor_str, object)?;
+        },
+        _ => {
+            return Err(format_err!("Unsupported activity type: {}", typ));
+        }
+    };
+
+    Ok(())
+}
+
+fn now() -> i32 {
+    Utc::now().timestamp() as i32
+}
+
+fn record_failure(conn: &SqliteConnection, activity: &models::Activity, error: &str) -> Result<(), ::failure::Error> {
+    let existing = schema::inbox_failed::table
+        .filter(schema::inbox_failed::id.eq(&activity.id))
+        .first::<models::FailedActivity>(conn)
+        .optional()?;
+
+    match existing {
+        Some(failed) => {
+            let failed_q = schema::inbox_failed::table.filter(
+                schema::inbox_failed::rowid.eq(failed.rowid)
+            );
+
+            ::diesel::update(failed_q)
+                .set((
+                    schema::inbox_failed::json.eq(&activity.json),
+                    schema::inbox_failed::error.eq(error),
+                    schema::inbox_failed::attempts.eq(failed.attempts + 1),
+                    schema::inbox_failed::last_failed.eq(now())
+                ))
+                .execute(conn)?;
+        },
+        None => {
+            let new_failed = models::NewFailedActivity {
+                id: activity.id.clone(),
+                json: activity.json.clone(),
+                error: error.to_owned()
+            };
+
+            ::diesel::insert_into(schema::inbox_failed::table)
+                .values(&new_failed)
+                .execute(conn)?;
+        }
+    };
+
+    Ok(())
+}
+
+fn process(config: &Config, conn: &SqliteConnection, worker: &InboxWorker, activity: &models::Activity) -> Result<(), ::failure::Error> {
+    let result = handle_activity(config, conn, activity)
+        .map_err(|e| format!("{}", e));
+
+    match result {
+        Ok(()) => {
+            println!("handle_activity(rowid = {}) succeeded", activity.rowid);
+        },
+        Err(ref e) => {
+            println!("handle_activity(rowid = {}) failed: {}", activity.rowid, e);
+        }
+    };
+
+    conn.transaction::<_, ::failure::Error, _>(|| {
+        match result {
+            Ok(()) => {
+                let failed_q = schema::inbox_failed::table.filter(
+                    schema::inbox_failed::id.eq(&activity.id)
+                );
+
+                ::diesel::delete(failed_q).execute(conn)?;
+            },
+            Err(ref e) => {
+                record_failure(conn, activity, e)?;
+            }
+        };
+
+        let new_processed = models::NewProcessedActivity {
+            id: activity.id.clone()
+        };
+
+        ::diesel::replace_into(schema::inbox_processed::table)
+            .values(&new_processed)
+            .execute(conn)?;
+
+        let delete_q = schema::inbox::table.filter(
+            schema::inbox::rowid.eq(activity.rowid)
+        );
+
+        ::diesel::delete(delete_q).execute(conn)?;
+
+        Ok(())
+    })?;
+
+    worker.record(ActivityOutcome {
+        rowid: activity.rowid,
+        id: activity.id.clone(),
+        error: result.err(),
+        datetime: Utc::now().timestamp()
+    });
+
+    Ok(())
+}
+
+pub fn is_duplicate(conn: &SqliteConnection, id: &str) -> Result<bool, ::diesel::result::Error> {
+    let queued = ::diesel::select(exists(
+        schema::inbox::table.filter(schema::inbox::id.eq(id))
+    )).get_result::<bool>(conn)?;
+
+    let processed = ::diesel::select(exists(
+        schema::inbox_processed::table.filter(schema::inbox_processed::id.eq(id))
+    )).get_result::<bool>(conn)?;
+
+    let failed = ::diesel::select(exists(
+        schema::inbox_failed::table.filter(schema::inbox_failed::id.eq(id))
+    )).get_result::<bool>(conn)?;
+
+    Ok(queued || processed || failed)
+}
+
+fn prune_processed(config: &Config, conn: &SqliteConnection) -> Result<(), ::failure::Error> {
+    let expired_q = schema::inbox_processed::table.filter(
+        schema::inbox_processed::datetime.lt(now() - config.processed_retention)
+    );
+
+    ::diesel::delete(expired_q).execute(conn)?;
+
+    Ok(())
+}
+
+pub fn retry_failed(conn: &SqliteConnection) -> Result<usize, ::failure::Error> {
+    let failed = schema::inbox_failed::table
+        .order(schema::inbox_failed::rowid.asc())
+        .load::<models::FailedActivity>(conn)?;
+
+    conn.transaction::<_, ::failure::Error, _>(|| {
+        for activity in failed.iter() {
+            let new_activity = models::NewActivity {
+                id: activity.id.clone(),
+                json: activity.json.clone()
+            };
+
+            ::diesel::replace_into(schema::inbox::table)
+                .values(&new_activity)
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(failed.len())
+}
+
+fn drain(config: &Config, pool: &SqlitePool, worker: &InboxWorker) -> Result<(), ::failure::Error> {
+    let conn = pool.get()?;
+
+    prune_processed(config, &conn)?;
+
+    while !worker.is_paused() {
+        let activities = schema::inbox::table
+            .order(schema::inbox::rowid.asc())
+            .limit(BATCH_SIZE)
+            .load::<models::Activity>(&conn)?;
+
+        if activities.is_empty() {
+            break;
+        }
+
+        for activity in activities.iter() {
+            if worker.is_paused() {
+                break;
+            }
+
+            process(config, &conn, worker, activity)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn spawn_worker(config: Config, pool: SqlitePool) -> InboxWorker {
+    let worker = InboxWorker {
+        state: Arc::new(WorkerState {
+            paused: AtomicBool::new(false),
+            pending: Mutex::new(true),
+            wakeup: Condvar::new(),
+            outcomes: Mutex::new(VecDeque::new())
+        })
+    };
+
+    let thread_worker = worker.clone();
+
+    thread::spawn(move || {
+        loop {
+            if !thread_worker.is_paused() {
+                if let Err(e) = drain(&config, &pool, &thread_worker) {
+                    println!("Inbox worker failed: {:?}", e);
+                }
+            }
+
+            thread_worker.wait();
+        }
+    });
+
+    worker
+}
@@ -4,62 +4,88 @@
 extern crate base64;
 extern crate bincode;
 extern crate chrono;
+extern crate claxon;
 #[macro_use] extern crate diesel;
 #[macro_use] extern crate failure;
+extern crate imagesize;
+extern crate lewton;
+extern crate multipart;
 extern crate openssl;
+extern crate png;
+extern crate puremp3;
 extern crate reqwest;
 extern crate rocket;
 extern crate rocket_contrib;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate serde_json;
+extern crate tera;
 extern crate url;
 extern crate webfinger;
 
 use failure::Error;
 
 pub mod api;
+pub mod audio;
 pub mod config;
 pub mod db;
+pub mod delivery;
+pub mod duration;
+pub mod inbox;
+pub mod media;
 pub mod models;
+pub mod ns;
 pub mod parser;
+pub mod password;
+pub mod remote;
+pub mod s3;
 pub mod schema;
 pub mod sig;
+pub mod sign;
+pub mod store;
+pub mod waveform;
 
 use config::Config;
 
 pub fn run(config: Config) -> Result<(), Error> {
     let pool = db::init_pool(&config)?;
 
-    /*
-    let conn = pool.get()?;
-    
-    use diesel::prelude::*;
-
-    let new_post = models::NewPost {
-        uri_name: "test_post".to_owned(),
-        title: "Test Post".to_owned(),
-        body: models::Body {
-            pieces: vec![
-                models::Piece::Html("<strong>hewwo!!!</strong>".to_owned())
-            ]
-        }
-    };
-    diesel::insert_into(schema::posts::table)
-        .values(&new_post)
-        .execute(&conn)
-        .unwrap()
-        ;
-
-    return Ok(());
-     */
+    delivery::spawn_worker(config.clone(), pool.clone());
+    let inbox_worker = inbox::spawn_worker(config.clone(), pool.clone());
+    let templates = api::html::Templates::load(&config)?;
+    let media_store = store::open(&config)?;
+    media::spawn_worker(config.clone(), pool.clone(), store::open(&config)?);
 
     rocket::ignite()
         .manage(config)
         .manage(pool)
+        .manage(inbox_worker)
+        .manage(templates)
+        .manage(media_store)
         .mount("/", api::activitypub::routes())
         .mount("/", api::webfinger::routes())
+        .mount("/", api::media::routes())
+        .mount("/", api::admin::routes())
+        .catch(api::admin::catchers())
         .launch();
 
     Ok(())
 }
+
+pub fn retry_failed(config: Config) -> Result<usize, Error> {
+    let pool = db::init_pool(&config)?;
+    let conn = pool.get()?;
+
+    inbox::retry_failed(&conn)
+}
+
+pub fn delete_post(config: Config, uri_name: &str) -> Result<(), Error> {
+    let pool = db::init_pool(&config)?;
+    let conn = pool.get()?;
+
+    api::activitypub::delete_post(&config, &conn, uri_name)
+}
+
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    password::hash(password)
+}
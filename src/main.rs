@@ -3,7 +3,7 @@ extern crate jadwiga;
 
 use std::env;
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 
 use jadwiga::config::RawConfig;
 
@@ -27,16 +27,79 @@ fn main() {
 
             pub_key
         },
+        priv_key: {
+            let priv_key_path = get_env("JADWIGA_PRIVATE_KEY");
+            let mut priv_key_file = File::open(priv_key_path)
+                .expect("Failed to open private key file");
+
+            let mut priv_key = String::new();
+            priv_key_file.read_to_string(&mut priv_key)
+                .expect("Failed to read private key file");
+
+            priv_key
+        },
         root_url: get_env("JADWIGA_ROOT_URL"),
         actor_username: get_env("JADWIGA_USERNAME"),
         actor_name: get_env("JADWIGA_NAME"),
-        media_dir: get_env("JADWIGA_MEDIA_DIR")
+        actor_bio: env::var("JADWIGA_BIO").ok(),
+        media_dir: get_env("JADWIGA_MEDIA_DIR"),
+        template_dir: env::var("JADWIGA_TEMPLATE_DIR").ok(),
+        admin_password_hash: env::var("JADWIGA_ADMIN_PASSWORD_HASH").ok(),
+        delivery_max_age: env::var("JADWIGA_DELIVERY_MAX_AGE").ok(),
+        processed_retention: env::var("JADWIGA_PROCESSED_RETENTION").ok(),
+        actor_ttl: env::var("JADWIGA_ACTOR_TTL").ok(),
+        signature_max_skew: env::var("JADWIGA_SIGNATURE_MAX_SKEW").ok(),
+        hide_followers: env::var("JADWIGA_HIDE_FOLLOWERS").ok(),
+        outbox_page_size: env::var("JADWIGA_OUTBOX_PAGE_SIZE").ok(),
+        media_store: env::var("JADWIGA_MEDIA_STORE").ok(),
+        s3_endpoint: env::var("JADWIGA_S3_ENDPOINT").ok(),
+        s3_region: env::var("JADWIGA_S3_REGION").ok(),
+        s3_bucket: env::var("JADWIGA_S3_BUCKET").ok(),
+        s3_access_key: env::var("JADWIGA_S3_ACCESS_KEY").ok(),
+        s3_secret_key: env::var("JADWIGA_S3_SECRET_KEY").ok(),
+        s3_redirect: env::var("JADWIGA_S3_REDIRECT").ok()
     };
 
     let config = raw_config.validate()
         .expect("Failed to validate config");
     
-    jadwiga::run(config)
-        .expect("Failed to run jadwiga");
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        Some("retry-failed") => {
+            let count = jadwiga::retry_failed(config)
+                .expect("Failed to requeue failed activities");
+
+            println!("Requeued {} failed activities", count);
+        },
+        Some("delete-post") => {
+            let uri_name = args.get(2)
+                .expect("Usage: delete-post <uri_name>");
+
+            jadwiga::delete_post(config, uri_name)
+                .expect("Failed to delete post");
+
+            println!("Deleted {}", uri_name);
+        },
+        Some("hash-password") => {
+            println!("Enter the admin password:");
+
+            let mut password = String::new();
+            io::stdin().read_line(&mut password)
+                .expect("Failed to read password");
+
+            let hash = jadwiga::hash_password(password.trim_right_matches(|c| c == '\r' || c == '\n'))
+                .expect("Failed to hash password");
+
+            println!("JADWIGA_ADMIN_PASSWORD_HASH={}", hash);
+        },
+        Some(command) => {
+            panic!("Unknown command: {}", command);
+        },
+        None => {
+            jadwiga::run(config)
+                .expect("Failed to run jadwiga");
+        }
+    };
 }
 
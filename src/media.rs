@@ -0,0 +1,344 @@
+use ::std::fs::{self, File, OpenOptions};
+use ::std::io::{self, Read, Write};
+use ::std::path::{Path, PathBuf};
+use ::std::thread;
+use ::std::time::Duration;
+
+use ::diesel::prelude::*;
+use ::diesel::result::{DatabaseErrorKind, Error as DieselError};
+use ::diesel::sqlite::SqliteConnection;
+use ::imagesize;
+use ::openssl::{rand, sha::Sha256};
+
+use audio::{self, AudioInfo};
+use config::Config;
+use db::SqlitePool;
+use duration;
+use models;
+use schema;
+use store::MediaStore;
+use waveform;
+
+// Uploads are stored under the SHA-256 of their contents, keeping the extension so
+// the file still says what it is:
+//
+// <sha256 hex>.mp3
+//
+// They're written to a temporary file in media_dir first, then put into the media
+// store once hashed.
+//
+// Uploading the same file twice gives back the first upload's record. Tracks also get
+// their tags and technical details read, with any embedded cover art uploaded too.
+//
+// Decoding a whole track takes too long for the upload request, so a worker computes
+// waveform peaks afterwards and draws the waveform once, storing it as
+// <sha256 hex>.waveform.png.
+
+const BUFFER_SIZE: usize = 64 * 1024;
+const WAVEFORM_POLL_INTERVAL: u64 = 10;
+const WAVEFORM_BATCH_SIZE: i64 = 5;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn guess_media_type(file: &str) -> &'static str {
+    let extension = Path::new(file)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "m4a" => "audio/mp4",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogv" => "video/ogg",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "application/octet-stream"
+    }
+}
+
+// only plain alphanumeric extensions make it into the stored name
+fn extension(filename: &str) -> Option<String> {
+    let extension = Path::new(filename)
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+
+    if !extension.is_empty() && extension.len() <= 8 && extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(extension)
+    } else {
+        None
+    }
+}
+
+fn temp_file(config: &Config) -> Result<(PathBuf, File), ::failure::Error> {
+    let mut suffix = [0; 8];
+    rand::rand_bytes(&mut suffix)?;
+
+    let temp_path = Path::new(&config.media_dir).join(format!(".upload-{}", to_hex(&suffix)));
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)?;
+
+    Ok((temp_path, file))
+}
+
+// copies the upload into the temp file, returning its hash and size
+fn write_hashed<R: Read>(reader: &mut R, file: &mut File) -> Result<(String, i64), io::Error> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size: i64 = 0;
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+        size += read as i64;
+    }
+
+    file.sync_all()?;
+
+    Ok((to_hex(&hasher.finish()), size))
+}
+
+// the hash part of a stored file's name
+pub fn file_hash(file: &str) -> &str {
+    file.split('.').next().unwrap_or(file)
+}
+
+pub fn get_by_file(conn: &SqliteConnection, file: &str) -> Result<Option<models::MediaFile>, ::failure::Error> {
+    Ok(schema::media::table
+        .filter(schema::media::file.eq(file))
+        .first::<models::MediaFile>(conn)
+        .optional()?)
+}
+
+pub fn get_by_hash(conn: &SqliteConnection, hash: &str) -> Result<Option<models::MediaFile>, ::failure::Error> {
+    Ok(schema::media::table
+        .filter(schema::media::hash.eq(hash))
+        .first::<models::MediaFile>(conn)
+        .optional()?)
+}
+
+pub fn store<R: Read>(config: &Config, media_store: &MediaStore, conn: &SqliteConnection, reader: &mut R, filename: &str, media_type: Option<&str>) -> Result<models::MediaFile, ::failure::Error> {
+    let (temp_path, mut temp) = temp_file(config)?;
+
+    let (hash, size) = match write_hashed(reader, &mut temp) {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.into());
+        }
+    };
+
+    if let Some(existing) = get_by_hash(conn, &hash)? {
+        fs::remove_file(&temp_path)?;
+        return Ok(existing);
+    }
+
+    let file = match extension(filename) {
+        Some(extension) => format!("{}.{}", hash, extension),
+        None => hash.clone()
+    };
+
+    // browsers send application/octet-stream for anything they don't recognise
+    let media_type = match media_type {
+        Some(media_type) if media_type != "application/octet-stream" => media_type.to_owned(),
+        _ => guess_media_type(filename).to_owned()
+    };
+
+    let (width, height) = if media_type.starts_with("image/") {
+        match imagesize::size(&temp_path) {
+            Ok(size) => (Some(size.width as i32), Some(size.height as i32)),
+            Err(_) => (None, None)
+        }
+    } else {
+        (None, None)
+    };
+
+    // unreadable tags just mean a track gets posted without them
+    let info = if media_type.starts_with("audio/") {
+        audio::read(&temp_path, &media_type)
+            .unwrap_or(None)
+            .unwrap_or_default()
+    } else {
+        AudioInfo::default()
+    };
+
+    // tags don't cover every format, so the container may have to say how long it is
+    let duration = match info.duration {
+        Some(duration) => Some(duration),
+        None => duration::read(&temp_path, &media_type).unwrap_or(None)
+    };
+
+    let cover = match info.cover {
+        Some(ref picture) => {
+            let cover_filename = format!("cover.{}", picture.media_type.trim_left_matches("image/").replace("jpeg", "jpg"));
+
+            match store(config, media_store, conn, &mut &picture.data[..], &cover_filename, Some(&picture.media_type)) {
+                Ok(cover) => Some(cover.file),
+                Err(e) => {
+                    let _ = fs::remove_file(&temp_path);
+                    return Err(e);
+                }
+            }
+        },
+        None => None
+    };
+
+    if let Err(e) = media_store.put(&file, &temp_path, &media_type) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    let new_media = models::NewMediaFile {
+        hash: hash.clone(),
+        file: file,
+        filename: filename.to_owned(),
+        media_type: media_type,
+        size: size,
+        width: width,
+        height: height,
+        duration: duration,
+        title: info.title,
+        artist: info.artist,
+        album: info.album,
+        track_number: info.track_number,
+        year: info.year,
+        genre: info.genre,
+        isrc: info.isrc,
+        cover: cover,
+        sample_rate: info.sample_rate,
+        channels: info.channels,
+        bitrate: info.bitrate
+    };
+
+    // a simultaneous upload of the same file may have got there first
+    let inserted = ::diesel::insert_into(schema::media::table)
+        .values(&new_media)
+        .execute(conn);
+
+    match inserted {
+        Ok(_) | Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {},
+        Err(e) => return Err(e.into())
+    };
+
+    let media_file = get_by_hash(conn, &hash)?
+        .ok_or(format_err!("Stored media {} went missing", hash))?;
+
+    // an upload that got there first under another extension leaves our copy unused
+    if media_file.file != new_media.file {
+        media_store.delete(&new_media.file)?;
+    }
+
+    Ok(media_file)
+}
+
+// copies a stored file out to a temporary file the decoders can open
+fn fetch(config: &Config, media_store: &MediaStore, key: &str) -> Result<PathBuf, ::failure::Error> {
+    let (temp_path, mut temp) = temp_file(config)?;
+
+    let copied = media_store.get(key)
+        .and_then(|mut reader| Ok(io::copy(&mut reader, &mut temp)?));
+
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    Ok(temp_path)
+}
+
+fn draw_waveform(config: &Config, media_store: &MediaStore, conn: &SqliteConnection, media_file: &models::MediaFile) -> Result<(), ::failure::Error> {
+    let track_path = fetch(config, media_store, &media_file.file)?;
+
+    // audio that can't be decoded still gets a flat line, so it isn't tried again
+    let peaks = match waveform::compute(&track_path, &media_file.media_type, media_file.sample_rate) {
+        Ok(peaks) => peaks,
+        Err(e) => {
+            println!("Failed to compute peaks for {}: {:?}", media_file.file, e);
+            None
+        }
+    };
+
+    fs::remove_file(&track_path)?;
+
+    let image = waveform::render_png(peaks.as_ref())?;
+    let waveform_file = format!("{}.waveform.png", media_file.hash);
+
+    let (image_path, mut image_temp) = temp_file(config)?;
+
+    let stored = image_temp.write_all(&image)
+        .map_err(::failure::Error::from)
+        .and_then(|_| media_store.put(&waveform_file, &image_path, "image/png"));
+
+    if let Err(e) = stored {
+        let _ = fs::remove_file(&image_path);
+        return Err(e);
+    }
+
+    let peaks = match peaks {
+        Some(peaks) => Some(::serde_json::to_string(&peaks)?),
+        None => None
+    };
+
+    ::diesel::update(schema::media::table.filter(schema::media::hash.eq(&media_file.hash)))
+        .set((
+            schema::media::peaks.eq(peaks),
+            schema::media::waveform.eq(Some(waveform_file))
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+fn draw_pending(config: &Config, media_store: &MediaStore, pool: &SqlitePool) -> Result<(), ::failure::Error> {
+    let conn = pool.get()?;
+
+    let pending = schema::media::table
+        .filter(schema::media::media_type.like("audio/%"))
+        .filter(schema::media::waveform.is_null())
+        .order(schema::media::uploaded.asc())
+        .limit(WAVEFORM_BATCH_SIZE)
+        .load::<models::MediaFile>(&conn)?;
+
+    // a track that fails is tried again next time, without holding up the rest
+    for media_file in pending.iter() {
+        if let Err(e) = draw_waveform(config, media_store, &conn, media_file) {
+            println!("Failed to draw waveform for {}: {:?}", media_file.file, e);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn spawn_worker(config: Config, pool: SqlitePool, media_store: Box<MediaStore>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        loop {
+            if let Err(e) = draw_pending(&config, &*media_store, &pool) {
+                println!("Waveform worker failed: {:?}", e);
+            }
+
+            thread::sleep(Duration::from_secs(WAVEFORM_POLL_INTERVAL));
+        }
+    })
+}
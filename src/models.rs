@@ -7,12 +7,36 @@ use ::diesel::serialize::{self, Output, ToSql};
 use ::diesel::sql_types::Binary;
 use ::serde_json::Value;
 
-use ::schema::{inbox, posts};
+use ::schema::{deliveries, followers, inbox, inbox_failed, inbox_processed, media, posts, remote_actors, tombstones};
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+// An uploaded media file. Duration is in seconds, for audio and video; width and
+// height are in pixels, for video and images
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Media {
+    pub file: String,
+    pub media_type: String,
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub alt: Option<String>,
+    pub caption: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Piece {
     Html(String),
-    MoreVariantsToCome
+    Audio(Media),
+    Video(Media),
+    Image(Media)
+}
+
+impl Piece {
+    pub fn media(&self) -> Option<&Media> {
+        match self {
+            Piece::Html(_) => None,
+            Piece::Audio(media) | Piece::Video(media) | Piece::Image(media) => Some(media)
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, FromSqlRow, AsExpression)]
@@ -82,3 +106,165 @@ impl NewActivity {
         Ok(NewActivity { id: id.to_owned(), json: json_str })
     }
 }
+
+#[derive(Debug, Queryable)]
+pub struct FailedActivity {
+    pub rowid: i32,
+    pub id: String,
+    pub json: String,
+    pub error: String,
+    pub attempts: i32,
+    pub first_failed: i32,
+    pub last_failed: i32
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="inbox_failed"]
+pub struct NewFailedActivity {
+    pub id: String,
+    pub json: String,
+    pub error: String
+}
+
+#[derive(Debug, Queryable)]
+pub struct ProcessedActivity {
+    pub id: String,
+    pub datetime: i32
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="inbox_processed"]
+pub struct NewProcessedActivity {
+    pub id: String
+}
+
+#[derive(Debug, Queryable)]
+pub struct Follower {
+    pub id: i32,
+    pub actor: String,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    pub follow_id: String,
+    pub datetime: i32
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="followers"]
+pub struct NewFollower {
+    pub actor: String,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    pub follow_id: String
+}
+
+#[derive(Debug, Queryable)]
+pub struct Delivery {
+    pub id: i32,
+    pub inbox: String,
+    pub json: String,
+    pub attempts: i32,
+    pub next_attempt: i32,
+    pub last_error: Option<String>,
+    pub datetime: i32
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="deliveries"]
+pub struct NewDelivery {
+    pub inbox: String,
+    pub json: String
+}
+
+#[derive(Debug, Queryable)]
+pub struct CachedActor {
+    pub id: String,
+    pub json: String,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    pub key_id: Option<String>,
+    pub public_key_pem: Option<String>,
+    pub fetched: i32
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="remote_actors"]
+pub struct NewCachedActor {
+    pub id: String,
+    pub json: String,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    pub key_id: Option<String>,
+    pub public_key_pem: Option<String>
+}
+
+#[derive(Debug, Queryable)]
+pub struct Tombstone {
+    pub uri_name: String,
+    pub title: String,
+    pub published: i32,
+    pub deleted: i32,
+    pub former_type: String
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="tombstones"]
+pub struct NewTombstone {
+    pub uri_name: String,
+    pub title: String,
+    pub published: i32,
+    pub former_type: String
+}
+
+#[derive(Debug, Queryable)]
+pub struct MediaFile {
+    pub hash: String,
+    pub file: String,
+    pub filename: String,
+    pub media_type: String,
+    pub size: i64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration: Option<f64>,
+    pub uploaded: i32,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<i32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>,
+    // the file of the cover art embedded in a track, stored as an upload of its own
+    pub cover: Option<String>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    // bits per second
+    pub bitrate: Option<i32>,
+    // waveform::Peaks as JSON
+    pub peaks: Option<String>,
+    // the file of the drawn waveform, set once the waveform worker has got to a track
+    pub waveform: Option<String>
+}
+
+#[derive(Debug, Insertable)]
+#[table_name="media"]
+pub struct NewMediaFile {
+    pub hash: String,
+    pub file: String,
+    pub filename: String,
+    pub media_type: String,
+    pub size: i64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration: Option<f64>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<i32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>,
+    pub cover: Option<String>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub bitrate: Option<i32>
+}
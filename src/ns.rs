@@ -0,0 +1,4 @@
+pub const ACTIVITYSTREAMS: &str = "https://www.w3.org/ns/activitystreams";
+pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+pub const SECURITY: &str = "https://w3id.org/security/v1";
@@ -1,95 +1,680 @@
 use ::std::collections::HashMap;
+use ::std::error;
+use ::std::fmt::{self, Display, Formatter};
 
-// Signature:
+use ::base64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    UnexpectedEof,
+    UnexpectedChar(char),
+    ExpectedKey,
+    ExpectedValue,
+    InvalidEscape,
+    InvalidNumber,
+    InvalidBase64,
+    DuplicateKey(String),
+    TrailingComma
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub kind: ErrorKind
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Parse error at position {}: {:?}", self.position, self.kind)
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        "parse error"
+    }
+}
+
+struct Cursor<'a> {
+    text: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Cursor<'a> {
+    fn new(text: &'a str) -> Self {
+        Cursor { text: text.as_bytes(), pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let ch = self.peek();
+        if ch.is_some() {
+            self.pos += 1;
+        }
+        ch
+    }
+
+    fn skip_sp(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_ows(&mut self) {
+        while self.peek() == Some(b' ') || self.peek() == Some(b'\t') {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, kind: ErrorKind) -> ParseError {
+        ParseError { position: self.pos, kind: kind }
+    }
+
+    // Reports whatever is at the cursor as unexpected
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(ch) => self.error(ErrorKind::UnexpectedChar(ch as char)),
+            None => self.error(ErrorKind::UnexpectedEof)
+        }
+    }
+}
+
+fn is_tchar(ch: u8) -> bool {
+    ch.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&ch)
+}
+
+fn is_lcalpha(ch: u8) -> bool {
+    ch >= b'a' && ch <= b'z'
+}
+
+// Signature (draft-cavage-http-signatures-12, section 4.1):
+//
 // keyId="https://my-example.com/actor#main-key",headers="(request-target) host date",signature="Y2FiYW...IxNGRiZDk4ZA=="
+//
+// The grammar also allows whitespace around ',' and '=', unquoted token values
+// such as created=1402170695, and backslash escapes inside quoted strings.
+//
+// Repeated parameters are an error, as required by the draft.
 
-pub fn sig(text: &str) -> Result<HashMap<String, String>, ::failure::Error> {
-    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-    enum State {
-        Key,
-        PreValue,
-        Value,
-        PostValue
+fn sig_token(cursor: &mut Cursor) -> String {
+    let mut token = String::new();
+
+    while let Some(ch) = cursor.peek() {
+        if !is_tchar(ch) {
+            break;
+        }
+        token.push(ch as char);
+        cursor.pos += 1;
     }
-    
-    let mut map = HashMap::new();
-    let mut state = State::Key;
-    let mut key = String::new();
-    let mut value = String::new();
-
-    let err = |state: State, ch: char| Err(format_err!("Signature parsing error: state = {:?}, ch = {:?}", state, ch));
-    
-    for ch in text.chars() {
-        match (state, ch) {
-            (State::Key, '=') => {
-                state = State::PreValue
-            },
-            (State::Key, _) => {
-                key.push(ch);
-            },
-            (State::PreValue, '"') => {
-                state = State::Value;
-            },
-            (State::PreValue, _) => {
-                return err(state, ch);
-            },
-            (State::Value, '"') => {
-                map.insert(key.clone(), value.clone());
-                state = State::PostValue
+
+    token
+}
+
+fn sig_quoted_string(cursor: &mut Cursor) -> Result<String, ParseError> {
+    cursor.pos += 1;
+
+    let mut bytes = vec![];
+
+    loop {
+        match cursor.next() {
+            Some(b'"') => break,
+            Some(b'\\') => match cursor.next() {
+                Some(ch) if ch == b'\t' || (ch >= 0x20 && ch != 0x7f) => bytes.push(ch),
+                Some(_) => {
+                    cursor.pos -= 1;
+                    return Err(cursor.error(ErrorKind::InvalidEscape));
+                },
+                None => return Err(cursor.error(ErrorKind::UnexpectedEof))
             },
-            (State::Value, _) => {
-                value.push(ch);
+            Some(ch) if ch == b'\t' || (ch >= 0x20 && ch != 0x7f) => bytes.push(ch),
+            Some(_) => {
+                cursor.pos -= 1;
+                return Err(cursor.unexpected());
             },
-            (State::PostValue, ',') => {
-                key.clear();
-                value.clear();
-                state = State::Key;
+            None => return Err(cursor.error(ErrorKind::UnexpectedEof))
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+pub fn sig(text: &str) -> Result<HashMap<String, String>, ParseError> {
+    let mut cursor = Cursor::new(text);
+    let mut map = HashMap::new();
+
+    loop {
+        cursor.skip_ows();
+
+        // Empty list elements (",,") are allowed by the HTTP list syntax
+        if cursor.peek() == Some(b',') {
+            cursor.pos += 1;
+            continue;
+        }
+
+        if cursor.peek().is_none() {
+            break;
+        }
+
+        let key_pos = cursor.pos;
+        let key = sig_token(&mut cursor);
+
+        if key.is_empty() {
+            return Err(cursor.error(ErrorKind::ExpectedKey));
+        }
+
+        cursor.skip_ows();
+
+        if cursor.peek() != Some(b'=') {
+            return Err(cursor.unexpected());
+        }
+        cursor.pos += 1;
+
+        cursor.skip_ows();
+
+        let value = match cursor.peek() {
+            Some(b'"') => sig_quoted_string(&mut cursor)?,
+            Some(ch) if is_tchar(ch) => sig_token(&mut cursor),
+            _ => return Err(cursor.error(ErrorKind::ExpectedValue))
+        };
+
+        if map.contains_key(&key) {
+            return Err(ParseError { position: key_pos, kind: ErrorKind::DuplicateKey(key) });
+        }
+
+        map.insert(key, value);
+
+        cursor.skip_ows();
+
+        match cursor.peek() {
+            None => break,
+            Some(b',') => {
+                cursor.pos += 1;
             },
-            (State::PostValue, _) => {
-                return err(state, ch);
+            Some(_) => return Err(cursor.unexpected())
+        };
+    }
+
+    if map.is_empty() {
+        return Err(cursor.error(ErrorKind::ExpectedKey));
+    }
+
+    Ok(map)
+}
+
+pub fn sig_headers(text: &str) -> Result<Vec<String>, ParseError> {
+    let headers = text.split(|ch: char| ch == ' ' || ch == '\t')
+        .filter(|header| !header.is_empty())
+        .map(|header| header.to_lowercase())
+        .collect::<Vec<String>>();
+
+    if headers.is_empty() {
+        return Err(ParseError { position: 0, kind: ErrorKind::ExpectedValue });
+    }
+
+    Ok(headers)
+}
+
+// Structured Field Values (RFC 8941), as used by RFC 9421 HTTP Message Signatures:
+//
+// Signature-Input: sig1=("@method" "@target-uri" "content-digest");created=1618884473;keyid="test-key-rsa"
+// Signature: sig1=:YmFzZTY0...:
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BareItem {
+    Integer(i64),
+    Decimal(f64),
+    String(String),
+    Token(String),
+    ByteSequence(Vec<u8>),
+    Boolean(bool)
+}
+
+pub type Parameters = Vec<(String, BareItem)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub bare_item: BareItem,
+    pub params: Parameters
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Member {
+    Item(Item),
+    InnerList(Vec<Item>, Parameters)
+}
+
+pub type Dictionary = Vec<(String, Member)>;
+
+fn sf_key(cursor: &mut Cursor) -> Result<String, ParseError> {
+    match cursor.peek() {
+        Some(ch) if is_lcalpha(ch) || ch == b'*' => {},
+        _ => return Err(cursor.error(ErrorKind::ExpectedKey))
+    };
+
+    let mut key = String::new();
+
+    while let Some(ch) = cursor.peek() {
+        if is_lcalpha(ch) || ch.is_ascii_digit() || b"_-.*".contains(&ch) {
+            key.push(ch as char);
+            cursor.pos += 1;
+        } else {
+            break;
+        }
+    }
+
+    Ok(key)
+}
+
+fn sf_number(cursor: &mut Cursor) -> Result<BareItem, ParseError> {
+    let start = cursor.pos;
+
+    let negative = cursor.peek() == Some(b'-');
+    if negative {
+        cursor.pos += 1;
+    }
+
+    let mut digits = String::new();
+    let mut is_decimal = false;
+
+    while let Some(ch) = cursor.peek() {
+        if ch.is_ascii_digit() {
+            digits.push(ch as char);
+        } else if ch == b'.' && !is_decimal {
+            if digits.len() > 12 {
+                return Err(cursor.error(ErrorKind::InvalidNumber));
             }
+            digits.push('.');
+            is_decimal = true;
+        } else {
+            break;
+        }
+        cursor.pos += 1;
+
+        if (!is_decimal && digits.len() > 15) || (is_decimal && digits.len() > 16) {
+            return Err(cursor.error(ErrorKind::InvalidNumber));
         }
     }
 
-    if state != State::PostValue {
-        return Err(format_err!("Reached EOF prematurely: state = {:?}", state));
+    if digits.is_empty() || digits.ends_with('.') {
+        return Err(cursor.error(ErrorKind::InvalidNumber));
+    }
+
+    let invalid = ParseError { position: start, kind: ErrorKind::InvalidNumber };
+    let sign = if negative { "-" } else { "" };
+
+    if is_decimal {
+        if digits.len() - digits.find('.').unwrap() - 1 > 3 {
+            return Err(invalid);
+        }
+        format!("{}{}", sign, digits).parse()
+            .map(BareItem::Decimal)
+            .map_err(|_| invalid)
+    } else {
+        format!("{}{}", sign, digits).parse()
+            .map(BareItem::Integer)
+            .map_err(|_| invalid)
     }
-    
-    Ok(map)
 }
 
-pub fn sig_headers(text: &str) -> Result<Vec<String>, ::failure::Error> {
-    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-    enum State {
-        Whitespace,
-        Header
-    }        
-    
-    let mut headers = vec![];
-    let mut state = State::Whitespace;
-    let mut header = String::new();
-    
-    for ch in text.chars() {
-        match (state, ch) {
-            (State::Whitespace, ' ') => {},
-            (State::Whitespace, _) => {
-                header.push(ch);
-                state = State::Header;
+fn sf_string(cursor: &mut Cursor) -> Result<BareItem, ParseError> {
+    cursor.pos += 1;
+
+    let mut string = String::new();
+
+    loop {
+        match cursor.next() {
+            Some(b'\\') => match cursor.next() {
+                Some(ch) if ch == b'"' || ch == b'\\' => string.push(ch as char),
+                Some(_) => {
+                    cursor.pos -= 1;
+                    return Err(cursor.error(ErrorKind::InvalidEscape));
+                },
+                None => return Err(cursor.error(ErrorKind::UnexpectedEof))
             },
-            (State::Header, ' ') => {
-                headers.push(header.clone());
-                header.clear();
-                state = State::Whitespace;
+            Some(b'"') => return Ok(BareItem::String(string)),
+            Some(ch) if ch >= 0x20 && ch < 0x7f => string.push(ch as char),
+            Some(_) => {
+                cursor.pos -= 1;
+                return Err(cursor.unexpected());
             },
-            (State::Header, _) => {
-                header.push(ch);
+            None => return Err(cursor.error(ErrorKind::UnexpectedEof))
+        }
+    }
+}
+
+fn sf_token(cursor: &mut Cursor) -> Result<BareItem, ParseError> {
+    let mut token = String::new();
+
+    while let Some(ch) = cursor.peek() {
+        if is_tchar(ch) || ch == b':' || ch == b'/' {
+            token.push(ch as char);
+            cursor.pos += 1;
+        } else {
+            break;
+        }
+    }
+
+    Ok(BareItem::Token(token))
+}
+
+fn sf_byte_sequence(cursor: &mut Cursor) -> Result<BareItem, ParseError> {
+    cursor.pos += 1;
+
+    let start = cursor.pos;
+
+    loop {
+        match cursor.next() {
+            Some(b':') => break,
+            Some(ch) if ch.is_ascii_alphanumeric() || ch == b'+' || ch == b'/' || ch == b'=' => {},
+            Some(_) => {
+                cursor.pos -= 1;
+                return Err(cursor.unexpected());
+            },
+            None => return Err(cursor.error(ErrorKind::UnexpectedEof))
+        }
+    }
+
+    let bytes = base64::decode(&cursor.text[start..cursor.pos - 1])
+        .map_err(|_| ParseError { position: start, kind: ErrorKind::InvalidBase64 })?;
+
+    Ok(BareItem::ByteSequence(bytes))
+}
+
+fn sf_bare_item(cursor: &mut Cursor) -> Result<BareItem, ParseError> {
+    match cursor.peek() {
+        Some(ch) if ch == b'-' || ch.is_ascii_digit() => sf_number(cursor),
+        Some(b'"') => sf_string(cursor),
+        Some(b'*') => sf_token(cursor),
+        Some(ch) if ch.is_ascii_alphabetic() => sf_token(cursor),
+        Some(b':') => sf_byte_sequence(cursor),
+        Some(b'?') => {
+            cursor.pos += 1;
+            match cursor.peek() {
+                Some(b'1') => { cursor.pos += 1; Ok(BareItem::Boolean(true)) },
+                Some(b'0') => { cursor.pos += 1; Ok(BareItem::Boolean(false)) },
+                _ => Err(cursor.unexpected())
             }
+        },
+        _ => Err(cursor.error(ErrorKind::ExpectedValue))
+    }
+}
+
+fn sf_parameters(cursor: &mut Cursor) -> Result<Parameters, ParseError> {
+    let mut params: Parameters = vec![];
+
+    while cursor.peek() == Some(b';') {
+        cursor.pos += 1;
+        cursor.skip_sp();
+
+        let key = sf_key(cursor)?;
+
+        let value = if cursor.peek() == Some(b'=') {
+            cursor.pos += 1;
+            sf_bare_item(cursor)?
+        } else {
+            BareItem::Boolean(true)
+        };
+
+        // RFC 8941 keeps the last value of a repeated key
+        match params.iter().position(|(k, _)| *k == key) {
+            Some(i) => params[i].1 = value,
+            None => params.push((key, value))
+        };
+    }
+
+    Ok(params)
+}
+
+fn sf_item(cursor: &mut Cursor) -> Result<Item, ParseError> {
+    let bare_item = sf_bare_item(cursor)?;
+    let params = sf_parameters(cursor)?;
+
+    Ok(Item { bare_item: bare_item, params: params })
+}
+
+fn sf_member(cursor: &mut Cursor) -> Result<Member, ParseError> {
+    if cursor.peek() != Some(b'(') {
+        return Ok(Member::Item(sf_item(cursor)?));
+    }
+
+    cursor.pos += 1;
+
+    let mut items = vec![];
+
+    loop {
+        cursor.skip_sp();
+
+        if cursor.peek() == Some(b')') {
+            cursor.pos += 1;
+            break;
         }
+
+        items.push(sf_item(cursor)?);
+
+        match cursor.peek() {
+            Some(b' ') | Some(b')') => {},
+            _ => return Err(cursor.unexpected())
+        };
+    }
+
+    let params = sf_parameters(cursor)?;
+
+    Ok(Member::InnerList(items, params))
+}
+
+pub fn sf_dictionary(text: &str) -> Result<Dictionary, ParseError> {
+    let mut cursor = Cursor::new(text);
+    let mut dictionary: Dictionary = vec![];
+
+    cursor.skip_sp();
+
+    while cursor.peek().is_some() {
+        let key = sf_key(&mut cursor)?;
+
+        let member = if cursor.peek() == Some(b'=') {
+            cursor.pos += 1;
+            sf_member(&mut cursor)?
+        } else {
+            let params = sf_parameters(&mut cursor)?;
+            Member::Item(Item { bare_item: BareItem::Boolean(true), params: params })
+        };
+
+        // RFC 8941 keeps the last value of a repeated key
+        match dictionary.iter().position(|(k, _)| *k == key) {
+            Some(i) => dictionary[i].1 = member,
+            None => dictionary.push((key, member))
+        };
+
+        cursor.skip_ows();
+
+        match cursor.peek() {
+            None => break,
+            Some(b',') => {
+                cursor.pos += 1;
+                cursor.skip_ows();
+                if cursor.peek().is_none() {
+                    return Err(cursor.error(ErrorKind::TrailingComma));
+                }
+            },
+            Some(_) => return Err(cursor.unexpected())
+        };
     }
 
-    if state == State::Header {
-        headers.push(header);
+    Ok(dictionary)
+}
+
+fn serialize_bare_item(bare_item: &BareItem) -> String {
+    match bare_item {
+        BareItem::Integer(i) => format!("{}", i),
+        BareItem::Decimal(d) => {
+            let text = format!("{:.3}", d);
+            let text = text.trim_right_matches('0');
+            if text.ends_with('.') { format!("{}0", text) } else { text.to_owned() }
+        },
+        BareItem::String(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+        BareItem::Token(t) => t.clone(),
+        BareItem::ByteSequence(b) => format!(":{}:", base64::encode(b)),
+        BareItem::Boolean(b) => if *b { "?1".to_owned() } else { "?0".to_owned() }
+    }
+}
+
+fn serialize_parameters(params: &Parameters) -> String {
+    params.iter()
+        .map(|(key, value)| match value {
+            BareItem::Boolean(true) => format!(";{}", key),
+            _ => format!(";{}={}", key, serialize_bare_item(value))
+        })
+        .collect()
+}
+
+pub fn serialize_item(item: &Item) -> String {
+    format!("{}{}", serialize_bare_item(&item.bare_item), serialize_parameters(&item.params))
+}
+
+pub fn serialize_inner_list(items: &[Item], params: &Parameters) -> String {
+    let items = items.iter()
+        .map(serialize_item)
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    format!("({}){}", items, serialize_parameters(params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sf_dictionary, sig, sig_headers, BareItem, ErrorKind, Item, Member, ParseError};
+
+    // Signature headers in the shape each server sends them, as (server, header, keyId, algorithm, headers)
+    const SIGNATURES: &[(&str, &str, &str, &str, &[&str])] = &[
+        (
+            "Mastodon",
+            "keyId=\"https://mastodon.social/users/Gargron#main-key\",algorithm=\"rsa-sha256\",headers=\"(request-target) host date digest content-type\",signature=\"pC7Yx8eQ6cPjg0qTnw3QK2L4pIuR6Q5jvWnLRpV4S8yqk0Cw1bT2oD7Qhk9dV1nGmE3YwXyZ0aFqR8sT2uVwXyZ1bC3dE5fG7hI9jK1lM3nO5pQ7rS9tU1vW3xY5zA7bC9dE1fG3hI5jK7lM9nO1pQ3rS5tU7vW9xY1zA3bC5dE7fG9hI==\"",
+            "https://mastodon.social/users/Gargron#main-key",
+            "rsa-sha256",
+            &["(request-target)", "host", "date", "digest", "content-type"]
+        ),
+        (
+            "Pleroma",
+            "keyId=\"https://pleroma.example/users/lain#main-key\",algorithm=\"rsa-sha256\",headers=\"(request-target) content-length date digest host\",signature=\"Lq3J8m1nE4wN0b6YkZ7VgC2rT5xP9dA1sH3uF6iK8oQ0eW2yR4tU6vX8zB1cD3fG5hJ7kL9mN1pS3qV5wY7aC9eI1gO3jM5lP7nR9tT1vX3zB5dF7hK9==\"",
+            "https://pleroma.example/users/lain#main-key",
+            "rsa-sha256",
+            &["(request-target)", "content-length", "date", "digest", "host"]
+        ),
+        (
+            "Misskey",
+            "keyId=\"https://misskey.io/users/7rkrarq81i#main-key\",algorithm=\"rsa-sha256\",headers=\"(request-target) date host digest\",signature=\"dW5rbm93bitzaWduYXR1cmUvYnl0ZXMrZnJvbStNaXNza2V5K2hlcmU9PT09PT0=\"",
+            "https://misskey.io/users/7rkrarq81i#main-key",
+            "rsa-sha256",
+            &["(request-target)", "date", "host", "digest"]
+        ),
+        (
+            "GoToSocial",
+            "keyId=\"https://gts.example.org/users/someone/main-key\",algorithm=\"hs2019\",headers=\"(request-target) host date digest\",signature=\"Z290b3NvY2lhbCtzaWduYXR1cmUrYnl0ZXMvZ29lcytoZXJlKzEyMzQ1Njc4OQ==\"",
+            "https://gts.example.org/users/someone/main-key",
+            "hs2019",
+            &["(request-target)", "host", "date", "digest"]
+        ),
+        (
+            "PeerTube",
+            "keyId=\"https://peertube.example/accounts/peertube\",algorithm=\"rsa-sha256\",headers=\"(request-target) host date digest\",signature=\"cGVlcnR1YmUrc2lnbmF0dXJlK2J5dGVzL2dvZXMraGVyZSsxMjM0NTY3ODk=\"",
+            "https://peertube.example/accounts/peertube",
+            "rsa-sha256",
+            &["(request-target)", "host", "date", "digest"]
+        )
+    ];
+
+    #[test]
+    fn server_signatures() {
+        for &(server, header, key_id, algorithm, headers) in SIGNATURES {
+            let fields = sig(header).unwrap_or_else(|e| panic!("{}: {}", server, e));
+
+            assert_eq!(fields.len(), 4, "{}", server);
+            assert_eq!(fields["keyId"], key_id, "{}", server);
+            assert_eq!(fields["algorithm"], algorithm, "{}", server);
+            assert!(fields["signature"].ends_with('='), "{}", server);
+            assert_eq!(sig_headers(&fields["headers"]).unwrap(), headers.to_vec(), "{}", server);
+        }
+    }
+
+    #[test]
+    fn sig_tokens_and_escapes() {
+        let fields = sig("keyId=\"a\\\"b\\\\c\",created=1402170695,expires=1402170995").unwrap();
+
+        assert_eq!(fields["keyId"], "a\"b\\c");
+        assert_eq!(fields["created"], "1402170695");
+        assert_eq!(fields["expires"], "1402170995");
+    }
+
+    #[test]
+    fn sig_whitespace() {
+        // OWS, tabs included, is allowed around ',' and '=', and empty list elements are skipped
+        let fields = sig("\tkeyId\t= \"a\" ,\t,signature =\t\"b\"\t").unwrap();
+
+        assert_eq!(fields["keyId"], "a");
+        assert_eq!(fields["signature"], "b");
+
+        assert_eq!(
+            sig_headers("(request-target)\thost  date\t").unwrap(),
+            vec!["(request-target)", "host", "date"]
+        );
+    }
+
+    #[test]
+    fn sig_errors() {
+        let error = |position, kind| Err(ParseError { position: position, kind: kind });
+
+        assert_eq!(sig("keyId=\"a\",keyId=\"b\""), error(10, ErrorKind::DuplicateKey("keyId".to_owned())));
+        assert_eq!(sig("keyId=\"a\\\u{7f}\""), error(9, ErrorKind::InvalidEscape));
+        assert_eq!(sig("keyId=\"a\\"), error(9, ErrorKind::UnexpectedEof));
+        assert_eq!(sig("keyId=\"a\u{1}\""), error(8, ErrorKind::UnexpectedChar('\u{1}')));
+        assert_eq!(sig("keyId \"a\""), error(6, ErrorKind::UnexpectedChar('"')));
+        assert_eq!(sig("keyId=,"), error(6, ErrorKind::ExpectedValue));
+        assert_eq!(sig("keyId=\"a\" signature=\"b\""), error(10, ErrorKind::UnexpectedChar('s')));
+        assert_eq!(sig(" , "), error(3, ErrorKind::ExpectedKey));
+        assert_eq!(sig_headers(" \t "), Err(ParseError { position: 0, kind: ErrorKind::ExpectedValue }));
+    }
+
+    fn string(s: &str) -> Item {
+        Item { bare_item: BareItem::String(s.to_owned()), params: vec![] }
+    }
+
+    #[test]
+    fn dictionary() {
+        let dictionary = sf_dictionary(
+            "sig1=(\"@method\" \"content-digest\");created=1618884473;keyid=\"test-key-rsa\",\t sig2=:3q2+7w==:;alg=ed25519, sig1=?0"
+        ).unwrap();
+
+        assert_eq!(dictionary, vec![
+            ("sig1".to_owned(), Member::Item(Item { bare_item: BareItem::Boolean(false), params: vec![] })),
+            ("sig2".to_owned(), Member::Item(Item {
+                bare_item: BareItem::ByteSequence(vec![0xde, 0xad, 0xbe, 0xef]),
+                params: vec![("alg".to_owned(), BareItem::Token("ed25519".to_owned()))]
+            }))
+        ]);
+
+        assert_eq!(sf_dictionary("sig1=(\"@method\" \"content-digest\");created=1618884473").unwrap(), vec![
+            ("sig1".to_owned(), Member::InnerList(
+                vec![string("@method"), string("content-digest")],
+                vec![("created".to_owned(), BareItem::Integer(1618884473))]
+            ))
+        ]);
+    }
+
+    #[test]
+    fn dictionary_errors() {
+        let error = |position, kind| Err(ParseError { position: position, kind: kind });
+
+        assert_eq!(sf_dictionary("sig1=\"a\\b\""), error(8, ErrorKind::InvalidEscape));
+        assert_eq!(sf_dictionary("sig1=\"a"), error(7, ErrorKind::UnexpectedEof));
+        // only SP separates inner list items
+        assert_eq!(sf_dictionary("sig1=(\"@method\"\t\"@path\")"), error(15, ErrorKind::UnexpectedChar('\t')));
+        assert_eq!(sf_dictionary("Sig1=?1"), error(0, ErrorKind::ExpectedKey));
+        assert_eq!(sf_dictionary("sig1=:AAAA:,\t"), error(13, ErrorKind::TrailingComma));
+        assert_eq!(sf_dictionary("sig1=:A:"), error(6, ErrorKind::InvalidBase64));
+        assert_eq!(sf_dictionary("sig1=1234567890123456"), error(21, ErrorKind::InvalidNumber));
+        assert_eq!(sf_dictionary("sig1=1.2345"), error(5, ErrorKind::InvalidNumber));
     }
-    
-    Ok(headers)
 }
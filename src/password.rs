@@ -0,0 +1,49 @@
+use ::base64;
+use ::openssl::{
+    hash::MessageDigest,
+    memcmp,
+    pkcs5,
+    rand
+};
+
+// Admin passwords are stored as PBKDF2 hashes:
+//
+// pbkdf2-sha256$<iterations>$<base64 salt>$<base64 hash>
+
+const SCHEME: &str = "pbkdf2-sha256";
+const ITERATIONS: usize = 100_000;
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 32;
+
+fn derive(password: &str, salt: &[u8], iterations: usize) -> Result<Vec<u8>, ::failure::Error> {
+    let mut key = vec![0; HASH_LENGTH];
+
+    pkcs5::pbkdf2_hmac(password.as_bytes(), salt, iterations, MessageDigest::sha256(), &mut key)?;
+
+    Ok(key)
+}
+
+pub fn hash(password: &str) -> Result<String, ::failure::Error> {
+    let mut salt = vec![0; SALT_LENGTH];
+    rand::rand_bytes(&mut salt)?;
+
+    let key = derive(password, &salt, ITERATIONS)?;
+
+    Ok(format!("{}${}${}${}", SCHEME, ITERATIONS, base64::encode(&salt), base64::encode(&key)))
+}
+
+pub fn verify(password: &str, hash: &str) -> Result<bool, ::failure::Error> {
+    let parts: Vec<&str> = hash.trim().split('$').collect();
+
+    if parts.len() != 4 || parts[0] != SCHEME {
+        return Err(format_err!("Password hash is not in the {} format", SCHEME));
+    }
+
+    let iterations: usize = parts[1].parse()?;
+    let salt = base64::decode(parts[2])?;
+    let expected = base64::decode(parts[3])?;
+
+    let key = derive(password, &salt, iterations)?;
+
+    Ok(key.len() == expected.len() && memcmp::eq(&key, &expected))
+}
@@ -0,0 +1,260 @@
+use ::std::time::Duration;
+
+use ::chrono::offset::Utc;
+use ::diesel::prelude::*;
+use ::diesel::sqlite::SqliteConnection;
+use ::reqwest::{header, mime};
+use ::serde_json::{self, Value};
+use ::url::Url;
+
+use config::Config;
+use models;
+use schema;
+use sign;
+
+const REQUEST_TIMEOUT: u64 = 10;
+
+pub const RFC9421: &str = "https://datatracker.ietf.org/doc/html/rfc9421";
+
+pub fn fetch_json(config: &Config, url: &str) -> Result<Value, ::failure::Error> {
+    let ld_json = "application/ld+json".parse::<mime::Mime>()?;
+    let parsed_url = Url::parse(url)?;
+    let signed_headers = sign::sign_request(config, "GET", &parsed_url, None)?;
+
+    let client = ::reqwest::Client::builder()
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+        .build()?;
+
+    let text = client
+        .get(url)
+        .headers(signed_headers)
+        .header(header::Accept(vec![
+            header::qitem(ld_json)
+        ]))
+        .send()
+        .map_err(|e| format_err!("Failed to fetch {}: {:?}", url, e))?
+        .text()
+        .map_err(|e| format_err!("Failed to get body of {}: {:?}", url, e))?;
+
+    let json: Value = serde_json::from_str(&text)
+        .map_err(|e| format_err!("Failed to parse {}: {:?}", url, e))?;
+
+    Ok(json)
+}
+
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub id: String,
+    pub owner: String,
+    pub pem: String
+}
+
+impl PublicKey {
+    pub fn from_json(json: &Value) -> Result<Self, ::failure::Error> {
+        let get_str = |field: &str| -> Result<String, ::failure::Error> {
+            Ok(json.get(field)
+                .ok_or(format_err!("No '{}' field found on public key", field))?
+                .as_str()
+                .ok_or(format_err!("Value of '{}' is not a string", field))?
+                .to_owned())
+        };
+
+        Ok(PublicKey {
+            id: get_str("id")?,
+            owner: get_str("owner")?,
+            pem: get_str("publicKeyPem")?
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct RemoteActor {
+    pub id: String,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    pub public_key: Option<PublicKey>,
+    pub json: Value
+}
+
+impl RemoteActor {
+    pub fn from_json(json: Value) -> Result<Self, ::failure::Error> {
+        let id = json.get("id")
+            .ok_or(format_err!("No 'id' field found on actor"))?
+            .as_str()
+            .ok_or(format_err!("Invalid non-string 'id' field on actor"))?
+            .to_owned();
+
+        let inbox = json.get("inbox")
+            .ok_or(format_err!("No 'inbox' field found on actor"))?
+            .as_str()
+            .ok_or(format_err!("Invalid non-string 'inbox' field on actor"))?
+            .to_owned();
+
+        let shared_inbox = json.get("endpoints")
+            .and_then(|endpoints| endpoints.get("sharedInbox"))
+            .and_then(Value::as_str)
+            .map(|s| s.to_owned());
+
+        let public_key = match json.get("publicKey") {
+            Some(key_json) => {
+                let key = PublicKey::from_json(key_json)?;
+
+                if key.owner != id {
+                    return Err(format_err!("Key owner {} is not the actor {}", key.owner, id));
+                }
+
+                Some(key)
+            },
+            None => None
+        };
+
+        Ok(RemoteActor {
+            id: id,
+            inbox: inbox,
+            shared_inbox: shared_inbox,
+            public_key: public_key,
+            json: json
+        })
+    }
+}
+
+// FEP-844e: actors list the specifications their software implements on `generator`
+pub fn supports_rfc9421(actor: &Value) -> bool {
+    actor.get("generator")
+        .and_then(|generator| generator.get("implements"))
+        .and_then(Value::as_array)
+        .map(|implements| implements.iter().any(|spec| {
+            spec.get("href")
+                .and_then(Value::as_str)
+                .map(|href| href == RFC9421)
+                .unwrap_or(false)
+        }))
+        .unwrap_or(false)
+}
+
+pub fn inbox_supports_rfc9421(conn: &SqliteConnection, inbox: &str) -> Result<bool, ::failure::Error> {
+    let actors = schema::remote_actors::table
+        .filter(
+            schema::remote_actors::inbox.eq(inbox)
+                .or(schema::remote_actors::shared_inbox.eq(inbox))
+        )
+        .load::<models::CachedActor>(conn)?;
+
+    for actor in actors {
+        let json: Value = serde_json::from_str(&actor.json)?;
+
+        if supports_rfc9421(&json) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn now() -> i32 {
+    Utc::now().timestamp() as i32
+}
+
+fn from_cache(config: &Config, cached: Option<models::CachedActor>) -> Result<Option<RemoteActor>, ::failure::Error> {
+    match cached {
+        Some(ref cached) if now() - cached.fetched < config.actor_ttl => {
+            let json: Value = serde_json::from_str(&cached.json)?;
+            Ok(Some(RemoteActor::from_json(json)?))
+        },
+        _ => Ok(None)
+    }
+}
+
+fn store(conn: &SqliteConnection, actor: &RemoteActor) -> Result<(), ::failure::Error> {
+    let new_cached = models::NewCachedActor {
+        id: actor.id.clone(),
+        json: serde_json::to_string(&actor.json)?,
+        inbox: actor.inbox.clone(),
+        shared_inbox: actor.shared_inbox.clone(),
+        key_id: actor.public_key.as_ref().map(|key| key.id.clone()),
+        public_key_pem: actor.public_key.as_ref().map(|key| key.pem.clone())
+    };
+
+    ::diesel::replace_into(schema::remote_actors::table)
+        .values(&new_cached)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn fetch_actor(config: &Config, url: &str) -> Result<RemoteActor, ::failure::Error> {
+    RemoteActor::from_json(fetch_json(config, url)?)
+}
+
+pub fn get_actor(config: &Config, conn: &SqliteConnection, id: &str, refresh: bool) -> Result<RemoteActor, ::failure::Error> {
+    if !refresh {
+        let cached = schema::remote_actors::table
+            .filter(schema::remote_actors::id.eq(id))
+            .first::<models::CachedActor>(conn)
+            .optional()?;
+
+        if let Some(actor) = from_cache(config, cached)? {
+            return Ok(actor);
+        }
+    }
+
+    let actor = fetch_actor(config, id)?;
+
+    if actor.id != id {
+        return Err(format_err!("Fetched actor has a different id: {}", actor.id));
+    }
+
+    store(conn, &actor)?;
+
+    Ok(actor)
+}
+
+pub fn get_actor_by_key(config: &Config, conn: &SqliteConnection, key_id: &str, refresh: bool) -> Result<RemoteActor, ::failure::Error> {
+    if !refresh {
+        let cached = schema::remote_actors::table
+            .filter(schema::remote_actors::key_id.eq(key_id))
+            .first::<models::CachedActor>(conn)
+            .optional()?;
+
+        if let Some(actor) = from_cache(config, cached)? {
+            return Ok(actor);
+        }
+    }
+
+    let document: Value = fetch_json(config, key_id)
+        .map_err(|e| format_err!("Failed to fetch key: {:?}", e))?;
+
+    let mut document_url = Url::parse(key_id)?;
+    document_url.set_fragment(None);
+
+    let document_id = document.get("id")
+        .and_then(Value::as_str)
+        .and_then(|id| Url::parse(id).ok());
+
+    // keyId usually dereferences to the actor, but it may also be a standalone key
+    // document. A document only speaks for the actor at its own url, so any other owner
+    // is fetched from its id and has to list the key itself
+    let actor = if document.get("publicKey").is_some() && document_id == Some(document_url) {
+        RemoteActor::from_json(document)?
+    } else {
+        let key = PublicKey::from_json(document.get("publicKey").unwrap_or(&document))?;
+
+        let actor = fetch_actor(config, &key.owner)
+            .map_err(|e| format_err!("Failed to fetch key owner: {:?}", e))?;
+
+        if actor.id != key.owner {
+            return Err(format_err!("Fetched key owner has a different id: {}", actor.id));
+        }
+
+        actor
+    };
+
+    match actor.public_key {
+        Some(ref key) if key.id == key_id => {},
+        _ => return Err(format_err!("Key {} is not listed on actor {}", key_id, actor.id))
+    };
+
+    store(conn, &actor)?;
+
+    Ok(actor)
+}
@@ -0,0 +1,368 @@
+use ::std::fs::{self, File};
+use ::std::path::Path;
+use ::std::time::{Duration, SystemTime};
+
+use ::chrono::{DateTime, Utc};
+use ::openssl::{
+    hash::{self, MessageDigest},
+    pkey::PKey,
+    sign::Signer
+};
+use ::reqwest::{self, header::{self, Headers}, Method, StatusCode};
+use ::url::Url;
+
+use config::S3Config;
+use sign;
+use store::{MediaStore, Reader, Stat};
+
+// Requests are signed with AWS Signature Version 4, leaving payloads unsigned:
+//
+// Authorization: AWS4-HMAC-SHA256 Credential=<access key>/20180727/us-east-1/s3/aws4_request,
+//                SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature=<hex>
+//
+// Presigned urls carry the same signature in their query string instead.
+
+const REQUEST_TIMEOUT: u64 = 300;
+const PRESIGNED_EXPIRY: u64 = 60 * 60;
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// percent-encodes everything but the unreserved characters, as SigV4 wants
+fn uri_encode(text: &str) -> String {
+    let mut encoded = String::new();
+
+    for byte in text.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+
+    encoded
+}
+
+fn hmac(key: &[u8], data: &str) -> Result<Vec<u8>, ::failure::Error> {
+    let key = PKey::hmac(key)?;
+
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data.as_bytes())?;
+
+    Ok(signer.sign_to_vec()?)
+}
+
+pub struct S3Store {
+    config: S3Config,
+    client: reqwest::Client
+}
+
+impl S3Store {
+    pub fn new(config: S3Config) -> Result<Self, ::failure::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .build()?;
+
+        Ok(S3Store {
+            config: config,
+            client: client
+        })
+    }
+
+    fn object_url(&self, key: &str) -> Result<Url, ::failure::Error> {
+        let mut url = self.config.endpoint.clone();
+
+        url.path_segments_mut()
+            .map_err(|_| format_err!("S3 endpoint {} can't have a path", self.config.endpoint))?
+            .pop_if_empty()
+            .push(&self.config.bucket)
+            .push(key);
+
+        Ok(url)
+    }
+
+    fn scope(&self, now: &DateTime<Utc>) -> String {
+        format!("{}/{}/s3/aws4_request", now.format("%Y%m%d"), self.config.region)
+    }
+
+    // headers must be lowercase and sorted by name
+    fn signature(&self, now: &DateTime<Utc>, method: &str, url: &Url, query: &str, headers: &[(&str, String)]) -> Result<String, ::failure::Error> {
+        let canonical_headers: String = headers.iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+
+        let signed_headers = headers.iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = [
+            method,
+            url.path(),
+            query,
+            &canonical_headers,
+            &signed_headers,
+            UNSIGNED_PAYLOAD
+        ].join("\n");
+
+        let string_to_sign = [
+            ALGORITHM.to_owned(),
+            now.format("%Y%m%dT%H%M%SZ").to_string(),
+            self.scope(now),
+            to_hex(&hash::hash(MessageDigest::sha256(), canonical_request.as_bytes())?)
+        ].join("\n");
+
+        let date_key = hmac(format!("AWS4{}", self.config.secret_key).as_bytes(), &now.format("%Y%m%d").to_string())?;
+        let region_key = hmac(&date_key, &self.config.region)?;
+        let service_key = hmac(&region_key, "s3")?;
+        let signing_key = hmac(&service_key, "aws4_request")?;
+
+        Ok(to_hex(&hmac(&signing_key, &string_to_sign)?))
+    }
+
+    fn request(&self, method: Method, key: &str, extra_headers: Headers, body: Option<reqwest::Body>) -> Result<reqwest::Response, ::failure::Error> {
+        let now = Utc::now();
+        let url = self.object_url(key)?;
+
+        let signed: Vec<(&str, String)> = vec![
+            ("host", sign::host(&url)?),
+            ("x-amz-content-sha256", UNSIGNED_PAYLOAD.to_owned()),
+            ("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string())
+        ];
+
+        let signature = self.signature(&now, method.as_ref(), &url, "", &signed)?;
+
+        let authorization = format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            ALGORITHM,
+            self.config.access_key,
+            self.scope(&now),
+            signed.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";"),
+            signature
+        );
+
+        let mut headers = extra_headers;
+
+        for (name, value) in signed {
+            headers.set_raw(name, value);
+        }
+
+        headers.set_raw("Authorization", authorization);
+
+        let mut request = self.client.request(method, url.as_str());
+        request.headers(headers);
+
+        if let Some(body) = body {
+            request.body(body);
+        }
+
+        let response = request.send()
+            .map_err(|e| format_err!("S3 request for {} failed: {:?}", key, e))?;
+
+        Ok(response)
+    }
+
+    fn get_object(&self, key: &str, headers: Headers) -> Result<Reader, ::failure::Error> {
+        let response = self.request(Method::Get, key, headers, None)?;
+
+        if !response.status().is_success() {
+            return Err(format_err!("Failed to get {} from S3: {}", key, response.status()));
+        }
+
+        Ok(Box::new(response))
+    }
+
+    fn presign(&self, key: &str) -> Result<String, ::failure::Error> {
+        let now = Utc::now();
+        let mut url = self.object_url(key)?;
+
+        let credential = format!("{}/{}", self.config.access_key, self.scope(&now));
+
+        // already in sorted order
+        let query = [
+            ("X-Amz-Algorithm", ALGORITHM.to_owned()),
+            ("X-Amz-Credential", credential),
+            ("X-Amz-Date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+            ("X-Amz-Expires", PRESIGNED_EXPIRY.to_string()),
+            ("X-Amz-SignedHeaders", "host".to_owned())
+        ].iter()
+            .map(|(name, value)| format!("{}={}", uri_encode(name), uri_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let headers = vec![("host", sign::host(&url)?)];
+        let signature = self.signature(&now, "GET", &url, &query, &headers)?;
+
+        url.set_query(Some(&format!("{}&X-Amz-Signature={}", query, signature)));
+
+        Ok(url.into_string())
+    }
+}
+
+impl MediaStore for S3Store {
+    fn put(&self, key: &str, source: &Path, media_type: &str) -> Result<(), ::failure::Error> {
+        let file = File::open(source)?;
+        let length = file.metadata()?.len();
+
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", media_type.to_owned());
+
+        let response = self.request(Method::Put, key, headers, Some(reqwest::Body::sized(file, length)))?;
+
+        if !response.status().is_success() {
+            return Err(format_err!("Failed to put {} to S3: {}", key, response.status()));
+        }
+
+        fs::remove_file(source)?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Reader, ::failure::Error> {
+        self.get_object(key, Headers::new())
+    }
+
+    fn get_range(&self, key: &str, start: u64, length: u64) -> Result<Reader, ::failure::Error> {
+        let mut headers = Headers::new();
+
+        if length > 0 {
+            headers.set_raw("Range", format!("bytes={}-{}", start, start + length - 1));
+        }
+
+        self.get_object(key, headers)
+    }
+
+    fn stat(&self, key: &str) -> Result<Option<Stat>, ::failure::Error> {
+        let response = self.request(Method::Head, key, Headers::new(), None)?;
+
+        if response.status() == StatusCode::NotFound {
+            return Ok(None);
+        }
+
+        if !response.status().is_success() {
+            return Err(format_err!("Failed to stat {} on S3: {}", key, response.status()));
+        }
+
+        let length = response.headers()
+            .get::<header::ContentLength>()
+            .map(|length| length.0)
+            .ok_or(format_err!("S3 sent no length for {}", key))?;
+
+        let modified = response.headers()
+            .get::<header::LastModified>()
+            .map(|last_modified| SystemTime::from(last_modified.0))
+            .ok_or(format_err!("S3 sent no modification time for {}", key))?;
+
+        Ok(Some(Stat {
+            length: length,
+            modified: DateTime::<Utc>::from(modified)
+        }))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), ::failure::Error> {
+        let response = self.request(Method::Delete, key, Headers::new(), None)?;
+
+        if !response.status().is_success() && response.status() != StatusCode::NotFound {
+            return Err(format_err!("Failed to delete {} from S3: {}", key, response.status()));
+        }
+
+        Ok(())
+    }
+
+    fn redirect_url(&self, key: &str) -> Result<Option<String>, ::failure::Error> {
+        if self.config.redirect {
+            Ok(Some(self.presign(key)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn nested_keys(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::env;
+    use ::std::fs::File;
+    use ::std::io::{Read, Write};
+
+    use ::chrono::Utc;
+    use ::reqwest::{self, StatusCode};
+    use ::url::Url;
+
+    use config::S3Config;
+    use store::MediaStore;
+    use super::S3Store;
+
+    const CONTENTS: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+    fn get_env(name: &str) -> String {
+        env::var(name).expect(&format!("{} must be set", name))
+    }
+
+    fn read_all(mut reader: ::store::Reader) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    // Needs a bucket that already exists, e.g. on a local MinIO:
+    //
+    // JADWIGA_S3_ENDPOINT=http://localhost:9000 JADWIGA_S3_BUCKET=jadwiga-test \
+    // JADWIGA_S3_ACCESS_KEY=minioadmin JADWIGA_S3_SECRET_KEY=minioadmin \
+    // cargo test s3 -- --ignored
+    #[test]
+    #[ignore]
+    fn minio() {
+        let store = S3Store::new(S3Config {
+            endpoint: Url::parse(&get_env("JADWIGA_S3_ENDPOINT")).unwrap(),
+            region: env::var("JADWIGA_S3_REGION").unwrap_or("us-east-1".to_owned()),
+            bucket: get_env("JADWIGA_S3_BUCKET"),
+            access_key: get_env("JADWIGA_S3_ACCESS_KEY"),
+            secret_key: get_env("JADWIGA_S3_SECRET_KEY"),
+            redirect: true
+        }).unwrap();
+
+        let now = Utc::now();
+        let key = format!("jadwiga-test-{}-{}.txt", now.timestamp(), now.timestamp_subsec_nanos());
+
+        let source = env::temp_dir().join(&key);
+        File::create(&source).unwrap().write_all(CONTENTS).unwrap();
+
+        assert!(store.stat(&key).unwrap().is_none());
+
+        store.put(&key, &source, "text/plain").unwrap();
+        assert!(!source.exists());
+
+        let stat = store.stat(&key).unwrap().unwrap();
+        assert_eq!(stat.length, CONTENTS.len() as u64);
+
+        assert_eq!(read_all(store.get(&key).unwrap()), CONTENTS);
+        assert_eq!(read_all(store.get_range(&key, 3, 4).unwrap()), b"defg");
+        assert_eq!(read_all(store.get_range(&key, 20, 6).unwrap()), b"uvwxyz");
+
+        let url = store.redirect_url(&key).unwrap().unwrap();
+
+        let mut response = reqwest::get(&url).unwrap();
+        assert_eq!(response.status(), StatusCode::Ok);
+
+        let mut body = Vec::new();
+        response.read_to_end(&mut body).unwrap();
+        assert_eq!(body, CONTENTS);
+
+        store.delete(&key).unwrap();
+        assert!(store.stat(&key).unwrap().is_none());
+        assert!(store.get(&key).is_err());
+
+        // already gone, which isn't an error
+        store.delete(&key).unwrap();
+
+        assert_eq!(reqwest::get(&url).unwrap().status(), StatusCode::NotFound);
+    }
+}
@@ -1,3 +1,26 @@
+table! {
+    deliveries (id) {
+        id -> Integer,
+        inbox -> Text,
+        json -> Text,
+        attempts -> Integer,
+        next_attempt -> Integer,
+        last_error -> Nullable<Text>,
+        datetime -> Integer,
+    }
+}
+
+table! {
+    followers (id) {
+        id -> Integer,
+        actor -> Text,
+        inbox -> Text,
+        shared_inbox -> Nullable<Text>,
+        follow_id -> Text,
+        datetime -> Integer,
+    }
+}
+
 table! {
     inbox (rowid) {
         rowid -> Integer,
@@ -6,6 +29,52 @@ table! {
     }
 }
 
+table! {
+    inbox_failed (rowid) {
+        rowid -> Integer,
+        id -> Text,
+        json -> Text,
+        error -> Text,
+        attempts -> Integer,
+        first_failed -> Integer,
+        last_failed -> Integer,
+    }
+}
+
+table! {
+    inbox_processed (id) {
+        id -> Text,
+        datetime -> Integer,
+    }
+}
+
+table! {
+    media (hash) {
+        hash -> Text,
+        file -> Text,
+        filename -> Text,
+        media_type -> Text,
+        size -> BigInt,
+        width -> Nullable<Integer>,
+        height -> Nullable<Integer>,
+        duration -> Nullable<Double>,
+        uploaded -> Integer,
+        title -> Nullable<Text>,
+        artist -> Nullable<Text>,
+        album -> Nullable<Text>,
+        track_number -> Nullable<Integer>,
+        year -> Nullable<Integer>,
+        genre -> Nullable<Text>,
+        isrc -> Nullable<Text>,
+        cover -> Nullable<Text>,
+        sample_rate -> Nullable<Integer>,
+        channels -> Nullable<Integer>,
+        bitrate -> Nullable<Integer>,
+        peaks -> Nullable<Text>,
+        waveform -> Nullable<Text>,
+    }
+}
+
 table! {
     posts (id) {
         id -> Integer,
@@ -16,7 +85,36 @@ table! {
     }
 }
 
+table! {
+    remote_actors (id) {
+        id -> Text,
+        json -> Text,
+        inbox -> Text,
+        shared_inbox -> Nullable<Text>,
+        key_id -> Nullable<Text>,
+        public_key_pem -> Nullable<Text>,
+        fetched -> Integer,
+    }
+}
+
+table! {
+    tombstones (uri_name) {
+        uri_name -> Text,
+        title -> Text,
+        published -> Integer,
+        deleted -> Integer,
+        former_type -> Text,
+    }
+}
+
 allow_tables_to_appear_in_same_query!(
+    deliveries,
+    followers,
     inbox,
+    inbox_failed,
+    inbox_processed,
+    media,
     posts,
+    remote_actors,
+    tombstones,
 );
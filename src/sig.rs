@@ -1,30 +1,84 @@
-use ::std::marker::PhantomData;
 use ::std::str::FromStr;
 
 use ::base64;
+use ::chrono::{DateTime, offset::Utc};
 use ::openssl::{
-    hash::MessageDigest,
-    pkey::{PKey, Public},
-    rsa::Rsa,
-    sign::Verifier
+    bn::BigNum,
+    ecdsa::EcdsaSig,
+    hash::{self, MessageDigest},
+    memcmp,
+    nid::Nid,
+    pkey::{Id, PKey, Public},
+    rsa::Padding,
+    sign::{RsaPssSaltlen, Verifier}
 };
 use ::rocket::{
+    http::Method,
     outcome::Outcome,
     request::{
         self,
         FromRequest,
         Request,
+        State
     }
 };
-use ::serde_json::{self, Value};
+use ::url::form_urlencoded;
 
 use api::error::Error;
-use parser;
+use config::Config;
+use db::Database;
+use parser::{self, BareItem, Member};
+use remote::{self, PublicKey};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    RsaSha256,
+    RsaPssSha512,
+    EcdsaP256Sha256,
+    Ed25519
+}
+
+impl Algorithm {
+    // draft-cavage names; hs2019 means "derive it from the key"
+    fn from_cavage(name: &str) -> Result<Option<Self>, ::failure::Error> {
+        match &name.to_lowercase() as &str {
+            "rsa-sha256" => Ok(Some(Algorithm::RsaSha256)),
+            "ed25519" => Ok(Some(Algorithm::Ed25519)),
+            "hs2019" => Ok(None),
+            _ => Err(format_err!("Unsupported signature algorithm: {}", name))
+        }
+    }
+
+    // RFC 9421 names
+    fn from_rfc9421(name: &str) -> Result<Self, ::failure::Error> {
+        match name {
+            "rsa-v1_5-sha256" => Ok(Algorithm::RsaSha256),
+            "rsa-pss-sha512" => Ok(Algorithm::RsaPssSha512),
+            "ecdsa-p256-sha256" => Ok(Algorithm::EcdsaP256Sha256),
+            "ed25519" => Ok(Algorithm::Ed25519),
+            _ => Err(format_err!("Unsupported signature algorithm: {}", name))
+        }
+    }
+
+    fn key_type(&self) -> Id {
+        match self {
+            Algorithm::RsaSha256 | Algorithm::RsaPssSha512 => Id::RSA,
+            Algorithm::EcdsaP256Sha256 => Id::EC,
+            Algorithm::Ed25519 => Id::ED25519
+        }
+    }
+}
+
+// Signature:
+// keyId="https://my-example.com/actor#main-key",algorithm="hs2019",created=1402170695,headers="(request-target) (created) host digest",signature="..."
 
 #[derive(Debug)]
 pub struct Signature {
     pub key_id: String,
+    pub algorithm: Option<String>,
     pub headers: Vec<String>,
+    pub created: Option<i64>,
+    pub expires: Option<i64>,
     pub signature: Vec<u8>
 }
 
@@ -38,9 +92,20 @@ impl FromStr for Signature {
             None => return Err(format_err!("No 'keyId' field found"))
         };
 
+        let created = match fields.get("created") {
+            Some(text) => Some(text.parse()?),
+            None => None
+        };
+
+        let expires = match fields.get("expires") {
+            Some(text) => Some(text.parse()?),
+            None => None
+        };
+
         let headers = match fields.get("headers") {
             Some(text) => parser::sig_headers(text)?,
-            None => return Err(format_err!("No 'headers' field found"))
+            None if created.is_some() => vec!["(created)".to_owned()],
+            None => vec!["date".to_owned()]
         };
 
         let signature_b64 = match fields.get("signature") {
@@ -52,118 +117,608 @@ impl FromStr for Signature {
 
         Ok(Signature {
             key_id: key_id.to_owned(),
+            algorithm: fields.get("algorithm").cloned(),
             headers: headers,
+            created: created,
+            expires: expires,
             signature: signature
         })
     }
 }
 
+// Signature-Input: sig1=("@method" "@target-uri" "content-digest");created=1618884473;keyid="test-key-rsa";alg="rsa-v1_5-sha256"
+// Signature: sig1=:...:
+
+#[derive(Debug)]
+pub struct MessageSignature {
+    pub label: String,
+    pub components: Vec<parser::Item>,
+    pub params: parser::Parameters,
+    pub signature: Vec<u8>
+}
+
+impl MessageSignature {
+    pub fn parse(signature_input: &str, signature: &str) -> Result<Self, ::failure::Error> {
+        let inputs = parser::sf_dictionary(signature_input)?;
+        let signatures = parser::sf_dictionary(signature)?;
+
+        for (label, input) in inputs.into_iter() {
+            let (components, params) = match input {
+                Member::InnerList(components, params) => (components, params),
+                _ => return Err(format_err!("Signature-Input '{}' is not an inner list", label))
+            };
+
+            let signature = signatures.iter()
+                .find(|(l, _)| *l == label)
+                .map(|(_, member)| member);
+
+            let bytes = match signature {
+                Some(Member::Item(parser::Item { bare_item: BareItem::ByteSequence(bytes), .. })) => bytes.clone(),
+                Some(_) => return Err(format_err!("Signature '{}' is not a byte sequence", label)),
+                None => continue
+            };
+
+            return Ok(MessageSignature {
+                label: label,
+                components: components,
+                params: params,
+                signature: bytes
+            });
+        }
+
+        Err(format_err!("No Signature found matching Signature-Input"))
+    }
+
+    fn param(&self, name: &str) -> Option<&BareItem> {
+        self.params.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    fn string_param(&self, name: &str) -> Result<Option<String>, ::failure::Error> {
+        match self.param(name) {
+            Some(BareItem::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(format_err!("Signature parameter '{}' is not a string", name)),
+            None => Ok(None)
+        }
+    }
+
+    fn integer_param(&self, name: &str) -> Result<Option<i64>, ::failure::Error> {
+        match self.param(name) {
+            Some(BareItem::Integer(i)) => Ok(Some(*i)),
+            Some(_) => Err(format_err!("Signature parameter '{}' is not an integer", name)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn key_id(&self) -> Result<String, ::failure::Error> {
+        self.string_param("keyid")?
+            .ok_or(format_err!("No 'keyid' parameter found"))
+    }
+
+    pub fn created(&self) -> Result<Option<i64>, ::failure::Error> {
+        self.integer_param("created")
+    }
+
+    pub fn expires(&self) -> Result<Option<i64>, ::failure::Error> {
+        self.integer_param("expires")
+    }
+
+    pub fn algorithm(&self) -> Result<Option<Algorithm>, ::failure::Error> {
+        match self.string_param("alg")? {
+            Some(name) => Ok(Some(Algorithm::from_rfc9421(&name)?)),
+            None => Ok(None)
+        }
+    }
+
+    pub fn component_names(&self) -> Result<Vec<String>, ::failure::Error> {
+        self.components.iter()
+            .map(|component| match component {
+                parser::Item { bare_item: BareItem::String(name), params } if params.is_empty() || query_param_name(component).is_some() =>
+                    Ok(name.clone()),
+                _ =>
+                    Err(format_err!("Unsupported covered component: {}", parser::serialize_item(component)))
+            })
+            .collect()
+    }
+
+    pub fn signature_params(&self) -> String {
+        parser::serialize_inner_list(&self.components, &self.params)
+    }
+}
+
+// "@query-param";name="..." is the one covered component with a parameter
+fn query_param_name(component: &parser::Item) -> Option<&str> {
+    match component {
+        parser::Item { bare_item: BareItem::String(name), params } if name == "@query-param" && params.len() == 1 => {
+            match params[0] {
+                (ref key, BareItem::String(ref param)) if key == "name" => Some(param),
+                _ => None
+            }
+        },
+        _ => None
+    }
+}
+
+// The body digest header a signature covers. A header that wasn't signed could have
+// been swapped along with the body, so only this one is worth checking.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignedDigest {
+    Digest(String),
+    ContentDigest(String)
+}
+
+impl SignedDigest {
+    pub fn verify(&self, body: &[u8]) -> Result<(), ::failure::Error> {
+        match self {
+            SignedDigest::Digest(header) => verify_digest(header, body),
+            SignedDigest::ContentDigest(header) => verify_content_digest(header, body)
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ValidSignature {
-    phantom: PhantomData<()>
+    pub key_id: String,
+    pub owner: String,
+    pub digest: Option<SignedDigest>
 }
 
-fn get_valid_signature<'a, 'r>(request: &'a Request<'r>) -> Result<ValidSignature, ::failure::Error> {
-    let headers = request.headers();
+impl ValidSignature {
+    pub fn verify_body(&self, body: &[u8]) -> Result<(), ::failure::Error> {
+        match self.digest {
+            Some(ref digest) => digest.verify(body),
+            None => Err(format_err!("The signature doesn't cover a digest of the body"))
+        }
+    }
+}
+
+// What both signature formats boil down to once parsed
+#[derive(Debug)]
+struct SignedRequest {
+    key_id: String,
+    algorithm: Option<Algorithm>,
+    signature_base: String,
+    signature: Vec<u8>,
+    created: Option<i64>,
+    expires: Option<i64>,
+    digest: Option<SignedDigest>
+}
 
-    println!("headers:\n");
-    for header in headers.iter() {
-        println!("{}: {}", header.name, header.value);
+fn check_digest(expected: &[u8], message_digest: MessageDigest, body: &[u8]) -> Result<(), ::failure::Error> {
+    let actual = hash::hash(message_digest, body)?;
+
+    if actual.len() != expected.len() || !memcmp::eq(&actual, expected) {
+        return Err(format_err!("Digest mismatch"));
     }
-    println!("");
-    
-    let signature_header = headers.get_one("Signature")
-        .ok_or(format_err!("No 'Signature' header found"))?;
 
-    let signature: Signature = signature_header.parse()
-        .map_err(|e| format_err!("Failed to parse Signature: {:?}", e))?;
+    Ok(())
+}
 
-    println!("key_id: {:?}", signature.key_id);
-    
-    let actor_str: String = {
-        use ::reqwest::{header, mime};
-
-        let ld_json = "application/ld+json".parse::<mime::Mime>()?;
-            
-        ::reqwest::Client::new()
-            .get(&signature.key_id)
-            .header(header::Accept(vec![
-                header::qitem(ld_json)
-            ]))
-            .send()
-            .map_err(|e| format_err!("Failed to fetch actor: {:?}", e))?
-            .text()
-            .map_err(|e| format_err!("Failed to get actor body: {:?}", e))?
-    };
-    
-    // println!("actor_str: {:?}", actor_str);
+pub fn verify_digest(digest_header: &str, body: &[u8]) -> Result<(), ::failure::Error> {
+    let mut checked = false;
+
+    for entry in digest_header.split(',') {
+        let mut parts = entry.trim().splitn(2, '=');
+
+        let algorithm = parts.next()
+            .unwrap_or("")
+            .to_lowercase();
+
+        let expected = match parts.next() {
+            Some(text) => base64::decode(text)?,
+            None => return Err(format_err!("Invalid Digest entry: {:?}", entry))
+        };
 
-    let actor: Value = serde_json::from_str(&actor_str)
-        .map_err(|e| format_err!("Failed to parse actor: {:?}", e))?;
+        let message_digest = match &algorithm as &str {
+            "sha-256" => MessageDigest::sha256(),
+            "sha-512" => MessageDigest::sha512(),
+            _ => continue
+        };
+
+        check_digest(&expected, message_digest, body)?;
 
-    let public_key_pem_json: &Value = actor
-        .get("publicKey")
-        .ok_or(format_err!("No 'publicKey' field found on actor"))?
-        .get("publicKeyPem")
-        .ok_or(format_err!("No 'publicKeyPem' field found on public key"))?;
+        checked = true;
+    }
+
+    if !checked {
+        return Err(format_err!("No supported algorithm found in Digest: {:?}", digest_header));
+    }
 
-    let public_key_pem: &[u8] = match public_key_pem_json {
-        Value::String(s) => s.as_bytes(),
-        _ => return Err(format_err!("Value of 'publicKeyPem' is not a string"))
+    Ok(())
+}
+
+pub fn verify_content_digest(content_digest_header: &str, body: &[u8]) -> Result<(), ::failure::Error> {
+    let mut checked = false;
+
+    for (algorithm, member) in parser::sf_dictionary(content_digest_header)? {
+        let expected = match member {
+            Member::Item(parser::Item { bare_item: BareItem::ByteSequence(bytes), .. }) => bytes,
+            _ => return Err(format_err!("Invalid Content-Digest entry: {:?}", algorithm))
+        };
+
+        let message_digest = match &algorithm as &str {
+            "sha-256" => MessageDigest::sha256(),
+            "sha-512" => MessageDigest::sha512(),
+            _ => continue
+        };
+
+        check_digest(&expected, message_digest, body)?;
+
+        checked = true;
+    }
+
+    if !checked {
+        return Err(format_err!("No supported algorithm found in Content-Digest: {:?}", content_digest_header));
+    }
+
+    Ok(())
+}
+
+fn parse_date(date: &str) -> Result<i64, ::failure::Error> {
+    let date = DateTime::parse_from_rfc2822(date)
+        .map_err(|e| format_err!("Failed to parse Date {:?}: {:?}", date, e))?;
+
+    Ok(date.timestamp())
+}
+
+fn check_created(created: i64, max_skew: i32) -> Result<(), ::failure::Error> {
+    let skew = (Utc::now().timestamp() - created).abs();
+
+    if skew > max_skew as i64 {
+        return Err(format_err!("Signature was created {} seconds away from the current time", skew));
+    }
+
+    Ok(())
+}
+
+fn check_expires(expires: i64) -> Result<(), ::failure::Error> {
+    if Utc::now().timestamp() > expires {
+        return Err(format_err!("Signature expired at {}", expires));
+    }
+
+    Ok(())
+}
+
+fn check_times(signed: &SignedRequest, max_skew: i32) -> Result<(), ::failure::Error> {
+    match signed.created {
+        Some(created) => check_created(created, max_skew)?,
+        None => return Err(format_err!("Either 'date' or the creation time must be signed"))
     };
 
-    let public_key_rsa: Rsa<Public> = Rsa::public_key_from_pem(public_key_pem)
-        .map_err(|e| format_err!("Failed to get RSA from PEM: {:?}", e))?;
+    if let Some(expires) = signed.expires {
+        check_expires(expires)?;
+    }
+
+    Ok(())
+}
+
+// The parts of a request that signatures cover
+trait Message {
+    fn method(&self) -> &str;
+    fn scheme(&self) -> &str;
+    // path and query
+    fn target(&self) -> &str;
+    fn header_values(&self, name: &str) -> Vec<&str>;
+
+    // only responses have one
+    fn status(&self) -> Option<u16> {
+        None
+    }
+}
+
+struct IncomingRequest<'a, 'r: 'a> {
+    request: &'a Request<'r>,
+    scheme: &'a str
+}
 
-    let public_key: PKey<Public> = PKey::from_rsa(public_key_rsa)
-        .map_err(|e| format_err!("Failed to get public key from RSA: {:?}", e))?;
-    
-    let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)
-        .map_err(|e| format_err!("Failed to create key verifier: {:?}", e))?;
+impl<'a, 'r> Message for IncomingRequest<'a, 'r> {
+    fn method(&self) -> &str {
+        self.request.method().as_str()
+    }
 
-    let mut required_headers = signature.headers.clone();
-    
-    if required_headers.iter().find(|h| *h == "date").is_none() {
-        required_headers.push("date".to_owned());
+    fn scheme(&self) -> &str {
+        self.scheme
     }
-    
-    let comparison_string: String = required_headers.iter()
+
+    fn target(&self) -> &str {
+        self.request.uri().as_str()
+    }
+
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.request.headers().get(name).collect()
+    }
+}
+
+fn header_value(message: &Message, name: &str) -> Result<String, ::failure::Error> {
+    let values = message.header_values(name)
+        .iter()
+        .map(|value| value.trim())
+        .collect::<Vec<&str>>();
+
+    if values.len() > 0 {
+        Ok(values.join(", "))
+    } else {
+        Err(format_err!("No '{}' header found", name))
+    }
+}
+
+fn cavage_signed_request(message: &Message, signature_header: &str) -> Result<SignedRequest, ::failure::Error> {
+    let signature: Signature = signature_header.parse()
+        .map_err(|e| format_err!("Failed to parse Signature: {:?}", e))?;
+
+    let algorithm = match signature.algorithm {
+        Some(ref name) => Algorithm::from_cavage(name)?,
+        None => None
+    };
+
+    let is_signed = |name: &str| signature.headers.iter()
+        .any(|h| h.to_lowercase() == name);
+
+    let created = if is_signed("(created)") {
+        Some(signature.created.ok_or(format_err!("No 'created' field found"))?)
+    } else if is_signed("date") {
+        Some(parse_date(&header_value(message, "date")?)?)
+    } else {
+        None
+    };
+
+    let digest = if is_signed("digest") {
+        Some(SignedDigest::Digest(header_value(message, "digest")?))
+    } else {
+        None
+    };
+
+    let comparison_string: String = signature.headers.iter()
         .map(|header_name| {
             let header_name = header_name.to_lowercase();
-            
-            let value_opt = if header_name == "(request-target)" {
-                let method = request.method().as_str().to_lowercase();
-                let uri = request.uri().as_str();
-                
-                Ok(format!("{} {}", method, uri))
-            } else {
-                let values = headers.get(&header_name)
-                    .map(|value| value.trim())
-                    .collect::<Vec<&str>>();
-
-                if values.len() > 0 {
-                    Ok(values.join(", "))
-                } else {
-                    Err(format_err!("No '{}' header found", header_name))
-                }
+
+            let value_opt = match &header_name as &str {
+                "(request-target)" => Ok(format!("{} {}", message.method().to_lowercase(), message.target())),
+                "(created)" => signature.created
+                    .map(|created| created.to_string())
+                    .ok_or(format_err!("No 'created' field found")),
+                "(expires)" => signature.expires
+                    .map(|expires| expires.to_string())
+                    .ok_or(format_err!("No 'expires' field found")),
+                _ => header_value(message, &header_name)
             };
 
             value_opt.map(|value| format!("{}: {}", header_name, value))
         })
         .collect::<Result<Vec<String>, _>>()?
         .join("\n");
-    
-    // println!("comparison_string: {:?}", comparison_string);
-    
-    verifier.update(comparison_string.as_bytes())?;
 
-    let verified = verifier.verify(&signature.signature)?;
-    
+    Ok(SignedRequest {
+        key_id: signature.key_id,
+        algorithm: algorithm,
+        signature_base: comparison_string,
+        signature: signature.signature,
+        created: created,
+        expires: signature.expires,
+        digest: digest
+    })
+}
+
+fn component_value(message: &Message, name: &str) -> Result<String, ::failure::Error> {
+    let target = message.target();
+
+    let (path, query) = match target.find('?') {
+        Some(index) => (&target[..index], &target[index + 1..]),
+        None => (target, "")
+    };
+
+    match name {
+        "@method" => Ok(message.method().to_uppercase()),
+        "@scheme" => Ok(message.scheme().to_owned()),
+        "@authority" => Ok(header_value(message, "host")?.to_lowercase()),
+        "@target-uri" => Ok(format!(
+            "{}://{}{}",
+            message.scheme(),
+            header_value(message, "host")?.to_lowercase(),
+            target
+        )),
+        "@request-target" => Ok(target.to_owned()),
+        "@path" => Ok(path.to_owned()),
+        "@query" => Ok(format!("?{}", query)),
+        "@status" => message.status()
+            .map(|status| status.to_string())
+            .ok_or(format_err!("@status is only covered on responses")),
+        _ if name.starts_with('@') => Err(format_err!("Unsupported derived component: {}", name)),
+        _ => header_value(message, name)
+    }
+}
+
+// The decoded value of a query parameter, encoded again the way RFC 9421 puts it
+fn query_param_value(message: &Message, param: &str) -> Result<String, ::failure::Error> {
+    let target = message.target();
+    let query = target.find('?')
+        .map(|index| &target[index + 1..])
+        .unwrap_or("");
+
+    let values = form_urlencoded::parse(query.as_bytes())
+        .filter(|(name, _)| name == param)
+        .map(|(_, value)| value.into_owned())
+        .collect::<Vec<String>>();
+
+    if values.len() != 1 {
+        return Err(format_err!("Expected one '{}' query parameter, found {}", param, values.len()));
+    }
+
+    Ok(form_urlencoded::byte_serialize(values[0].as_bytes())
+        .collect::<String>()
+        .replace('+', "%20"))
+}
+
+fn rfc9421_signed_request(message: &Message, signature_input: &str, signature_header: &str) -> Result<SignedRequest, ::failure::Error> {
+    let signature = MessageSignature::parse(signature_input, signature_header)
+        .map_err(|e| format_err!("Failed to parse Signature-Input: {:?}", e))?;
+
+    let key_id = signature.key_id()?;
+
+    let names = signature.component_names()?;
+    let is_covered = |name: &str| names.iter().any(|n| n == name);
+
+    let expires = signature.expires()?;
+
+    let created = match signature.created()? {
+        Some(created) => Some(created),
+        None if is_covered("date") => Some(parse_date(&header_value(message, "date")?)?),
+        None => None
+    };
+
+    let digest = if is_covered("content-digest") {
+        Some(SignedDigest::ContentDigest(header_value(message, "content-digest")?))
+    } else if is_covered("digest") {
+        Some(SignedDigest::Digest(header_value(message, "digest")?))
+    } else {
+        None
+    };
+
+    let mut lines = signature.components.iter()
+        .zip(names.iter())
+        .map(|(component, name)| {
+            let value = match query_param_name(component) {
+                Some(param) => query_param_value(message, param),
+                None => component_value(message, name)
+            };
+
+            value.map(|value| format!("{}: {}", parser::serialize_item(component), value))
+        })
+        .collect::<Result<Vec<String>, _>>()?;
+
+    lines.push(format!("\"@signature-params\": {}", signature.signature_params()));
+
+    Ok(SignedRequest {
+        key_id: key_id,
+        algorithm: signature.algorithm()?,
+        signature_base: lines.join("\n"),
+        signature: signature.signature,
+        created: created,
+        expires: expires,
+        digest: digest
+    })
+}
+
+fn signed_request(message: &Message) -> Result<SignedRequest, ::failure::Error> {
+    let signature_header = header_value(message, "signature")?;
+
+    if message.header_values("signature-input").is_empty() {
+        cavage_signed_request(message, &signature_header)
+    } else {
+        rfc9421_signed_request(message, &header_value(message, "signature-input")?, &signature_header)
+    }
+}
+
+fn verify(key: &PublicKey, signed: &SignedRequest) -> Result<bool, ::failure::Error> {
+    let public_key: PKey<Public> = PKey::public_key_from_pem(key.pem.as_bytes())
+        .map_err(|e| format_err!("Failed to get public key from PEM: {:?}", e))?;
+
+    let algorithm = match signed.algorithm {
+        Some(algorithm) => algorithm,
+        None => match public_key.id() {
+            Id::RSA => Algorithm::RsaSha256,
+            Id::ED25519 => Algorithm::Ed25519,
+            _ => return Err(format_err!("Unsupported key type"))
+        }
+    };
+
+    if public_key.id() != algorithm.key_type() {
+        return Err(format_err!("Key type does not match algorithm {:?}", algorithm));
+    }
+
+    let data = signed.signature_base.as_bytes();
+
+    let verified = match algorithm {
+        Algorithm::RsaSha256 => {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
+            verifier.update(data)?;
+            verifier.verify(&signed.signature)?
+        },
+        Algorithm::RsaPssSha512 => {
+            let mut verifier = Verifier::new(MessageDigest::sha512(), &public_key)?;
+            verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+            verifier.set_rsa_mgf1_md(MessageDigest::sha512())?;
+            verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+            verifier.update(data)?;
+            verifier.verify(&signed.signature)?
+        },
+        Algorithm::EcdsaP256Sha256 => {
+            if public_key.ec_key()?.group().curve_name() != Some(Nid::X9_62_PRIME256V1) {
+                return Err(format_err!("Key is not on the P-256 curve"));
+            }
+
+            // RFC 9421 signatures are r and s side by side, where openssl wants DER
+            if signed.signature.len() != 64 {
+                return Ok(false);
+            }
+
+            let signature = EcdsaSig::from_private_components(
+                BigNum::from_slice(&signed.signature[..32])?,
+                BigNum::from_slice(&signed.signature[32..])?
+            )?;
+
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
+            verifier.update(data)?;
+            verifier.verify(&signature.to_der()?)?
+        },
+        Algorithm::Ed25519 => {
+            let mut verifier = Verifier::new_without_digest(&public_key)?;
+            verifier.verify_oneshot(&signed.signature, data)?
+        }
+    };
+
+    Ok(verified)
+}
+
+fn get_valid_signature<'a, 'r>(request: &'a Request<'r>) -> Result<ValidSignature, ::failure::Error> {
+    let config = match request.guard::<State<Config>>() {
+        Outcome::Success(config) => config,
+        _ => return Err(format_err!("Failed to get config"))
+    };
+
+    let message = IncomingRequest {
+        request: request,
+        scheme: config.root_url.scheme()
+    };
+
+    let signed = signed_request(&message)?;
+    check_times(&signed, config.signature_max_skew)?;
+
+    if request.method() == Method::Post && signed.digest.is_none() {
+        return Err(format_err!("The 'digest' or 'content-digest' header must be signed"));
+    }
+
+    let database = match request.guard::<Database>() {
+        Outcome::Success(database) => database,
+        _ => return Err(format_err!("Failed to get database"))
+    };
+
+    let get_key = |refresh: bool| -> Result<PublicKey, ::failure::Error> {
+        let actor = remote::get_actor_by_key(&config, &database.conn, &signed.key_id, refresh)?;
+
+        actor.public_key
+            .ok_or(format_err!("No public key found on actor {}", actor.id))
+    };
+
+    let mut key = get_key(false)?;
+    let mut verified = verify(&key, &signed).unwrap_or(false);
+
+    if !verified {
+        // The key may have been rotated since it was cached
+        key = get_key(true)?;
+        verified = verify(&key, &signed)?;
+    }
+
     if !verified {
         return Err(format_err!("Failed to verify signature"));
     }
-    
-    Ok(ValidSignature {phantom: PhantomData})
+
+    Ok(ValidSignature {
+        key_id: key.id,
+        owner: key.owner,
+        digest: signed.digest
+    })
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for ValidSignature {
@@ -172,11 +727,354 @@ impl<'a, 'r> FromRequest<'a, 'r> for ValidSignature {
         let result: Result<Self, Self::Error> = get_valid_signature(request)
             .map_err(Error::bad_request);
 
-        println!("result: {:?}", result);
-
         match result {
             Ok(value) => Outcome::Success(value),
             Err(e) => Outcome::Failure((e.status(), e))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use remote::PublicKey;
+    use super::{
+        rfc9421_signed_request, signed_request, verify, verify_content_digest,
+        Algorithm, Message, MessageSignature, SignedDigest, ValidSignature
+    };
+
+    // The request from RFC 9421 appendix B.2
+    const CONTENT_DIGEST: &str = "sha-512=:WZDPaVn/7XgHaAy8pmojAkGWoRx2UFChF41A2svX+TaPm+AbwAgBWnrIiYllu7BNNyealdVLvRwEmTHWXvJwew==:";
+    const BODY: &[u8] = b"{\"hello\": \"world\"}";
+
+    // test-key-ed25519 from appendix B.1.4
+    const ED25519_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEAJrQLj5P/89iXES9+vFgrIy29clF9CC/oPPsw3c5D0bs=
+-----END PUBLIC KEY-----";
+
+    // test-key-rsa from appendix B.1.1, as a SubjectPublicKeyInfo
+    const RSA_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAhAKYdtoeoy8zcAcR874L
+8cnZxKzAGwd7v36APp7Pv6Q2jdsPBRrwWEBnez6d0UDKDwGbc6nxfEXAy5mbhgaj
+zrw3MOEt8uA5txSKobBpKDeBLOsdJKFqMGmXCQvEG7YemcxDTRPxAleIAgYYRjTS
+d/QBwVW9OwNFhekro3RtlinV0a75jfZgkne/YiktSvLG34lw2zqXBDTC5NHROUqG
+TlML4PlNZS5Ri2U4aCNx2rUPRcKIlE0PuKxI4T+HIaFpv8+rdV6eUgOrB2xeI1dS
+FFn/nnv5OoZJEIB+VmuKn3DCUcCZSFlQPSXSfBDiUGhwOw76WuSSsf1D4b/vLoJ1
+0wIDAQAB
+-----END PUBLIC KEY-----";
+
+    // test-key-rsa-pss from appendix B.1.2
+    const RSA_PSS_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAr4tmm3r20Wd/PbqvP1s2
++QEtvpuRaV8Yq40gjUR8y2Rjxa6dpG2GXHbPfvMs8ct+Lh1GH45x28Rw3Ry53mm+
+oAXjyQ86OnDkZ5N8lYbggD4O3w6M6pAvLkhk95AndTrifbIFPNU8PPMO7OyrFAHq
+gDsznjPFmTOtCEcN2Z1FpWgchwuYLPL+Wokqltd11nqqzi+bJ9cvSKADYdUAAN5W
+Utzdpiy6LbTgSxP7ociU4Tn0g5I6aDZJ7A8Lzo0KSyZYoA485mqcO0GVAdVw9lq4
+aOT9v6d+nb4bnNkQVklLQ3fVAvJm+xdDOp9LCNCN48V2pnDOkFV6+U9nV5oyc6XI
+2wIDAQAB
+-----END PUBLIC KEY-----";
+
+    // test-key-ecc-p256 from appendix B.1.3
+    const ECC_P256_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEqIVYZVLCrPZHGHjP17CTW0/+D9Lf
+w0EkjqF7xB4FivAxzic30tMM4GF+hR6Dxh71Z50VGGdldkkDXZCnTNnoXQ==
+-----END PUBLIC KEY-----";
+
+    struct TestMessage {
+        method: &'static str,
+        target: &'static str,
+        status: Option<u16>,
+        headers: Vec<(&'static str, String)>
+    }
+
+    impl Message for TestMessage {
+        fn method(&self) -> &str {
+            self.method
+        }
+
+        fn scheme(&self) -> &str {
+            "https"
+        }
+
+        fn target(&self) -> &str {
+            self.target
+        }
+
+        fn header_values(&self, name: &str) -> Vec<&str> {
+            self.headers.iter()
+                .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value as &str)
+                .collect()
+        }
+
+        fn status(&self) -> Option<u16> {
+            self.status
+        }
+    }
+
+    fn example_request(extra_headers: Vec<(&'static str, &str)>) -> TestMessage {
+        let mut headers = vec![
+            ("Host", "example.com".to_owned()),
+            ("Date", "Tue, 20 Apr 2021 02:07:55 GMT".to_owned()),
+            ("Content-Type", "application/json".to_owned()),
+            ("Content-Digest", CONTENT_DIGEST.to_owned()),
+            ("Content-Length", "18".to_owned())
+        ];
+
+        headers.extend(extra_headers.into_iter().map(|(name, value)| (name, value.to_owned())));
+
+        TestMessage {
+            method: "POST",
+            target: "/foo?param=Value&Pet=dog",
+            status: None,
+            headers: headers
+        }
+    }
+
+    fn public_key(pem: &str) -> PublicKey {
+        PublicKey {
+            id: "https://example.com/actor#main-key".to_owned(),
+            owner: "https://example.com/actor".to_owned(),
+            pem: pem.to_owned()
+        }
+    }
+
+    #[test]
+    fn signature_input() {
+        let signature = MessageSignature::parse(
+            "sig1=(\"@method\" \"@target-uri\" \"content-digest\");created=1618884473;keyid=\"test-key-rsa\";alg=\"rsa-v1_5-sha256\", \
+             sig2=(\"@method\");created=1618884475;keyid=\"test-key-ed25519\"",
+            "sig2=:AAAA:, sig1=:3q2+7w==:"
+        ).unwrap();
+
+        assert_eq!(signature.label, "sig1");
+        assert_eq!(signature.signature, vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(signature.component_names().unwrap(), vec!["@method", "@target-uri", "content-digest"]);
+        assert_eq!(signature.key_id().unwrap(), "test-key-rsa");
+        assert_eq!(signature.created().unwrap(), Some(1618884473));
+        assert_eq!(signature.expires().unwrap(), None);
+        assert_eq!(signature.algorithm().unwrap(), Some(Algorithm::RsaSha256));
+        assert_eq!(
+            signature.signature_params(),
+            "(\"@method\" \"@target-uri\" \"content-digest\");created=1618884473;keyid=\"test-key-rsa\";alg=\"rsa-v1_5-sha256\""
+        );
+    }
+
+    #[test]
+    fn signature_input_errors() {
+        // no signature with a matching label
+        assert!(MessageSignature::parse("sig1=(\"@method\");keyid=\"k\"", "sig2=:AAAA:").is_err());
+        // not an inner list
+        assert!(MessageSignature::parse("sig1=\"@method\"", "sig1=:AAAA:").is_err());
+        // not a byte sequence
+        assert!(MessageSignature::parse("sig1=(\"@method\");keyid=\"k\"", "sig1=\"AAAA\"").is_err());
+
+        let signature = MessageSignature::parse("sig1=(\"content-type\";sf);alg=\"hmac-sha256\"", "sig1=:AAAA:").unwrap();
+
+        assert!(signature.key_id().is_err());
+        assert!(signature.algorithm().is_err());
+        assert!(signature.component_names().is_err());
+    }
+
+    // B.2.2
+    #[test]
+    fn rsa_pss_sha512_selective() {
+        let message = example_request(vec![
+            ("Signature-Input", "sig-b22=(\"@authority\" \"content-digest\" \"@query-param\";name=\"Pet\");created=1618884473;keyid=\"test-key-rsa-pss\";tag=\"header-example\""),
+            ("Signature", "sig-b22=:LjbtqUbfmvjj5C5kr1Ugj4PmLYvx9wVjZvD9GsTT4F7GrcQEdJzgI9qHxICagShLRiLMlAJjtq6N4CDfKtjvuJyE5qH7KT8UCMkSowOB4+ECxCmT8rtAmj/0PIXxi0A0nxKyB09RNrCQibbUjsLS/2YyFYXEu4TRJQzRw1rLEuEfY17SARYhpTlaqwZVtR8NV7+4UKkjqpcAoFqWFQh62s7Cl+H2fjBSpqfZUJcsIk4N6wiKYd4je2U/lankenQ99PZfB4jY3I5rSV2DSBVkSFsURIjYErOs0tFTQosMTAoxk//0RoKUqiYY8Bh0aaUEb0rQl3/XaVe4bXTugEjHSw==:")
+        ]);
+
+        let mut signed = signed_request(&message).unwrap();
+
+        assert_eq!(signed.signature_base, [
+            "\"@authority\": example.com",
+            &format!("\"content-digest\": {}", CONTENT_DIGEST),
+            "\"@query-param\";name=\"Pet\": dog",
+            "\"@signature-params\": (\"@authority\" \"content-digest\" \"@query-param\";name=\"Pet\");created=1618884473;keyid=\"test-key-rsa-pss\";tag=\"header-example\""
+        ].join("\n"));
+
+        // no alg parameter, and an RSA key would otherwise mean rsa-v1_5-sha256
+        signed.algorithm = Some(Algorithm::RsaPssSha512);
+
+        assert!(verify(&public_key(RSA_PSS_KEY), &signed).unwrap());
+        assert_eq!(signed.digest, Some(SignedDigest::ContentDigest(CONTENT_DIGEST.to_owned())));
+    }
+
+    // B.2.3
+    #[test]
+    fn rsa_pss_sha512() {
+        let message = example_request(vec![
+            ("Signature-Input", "sig-b23=(\"date\" \"@method\" \"@path\" \"@query\" \"@authority\" \"content-type\" \"content-digest\" \"content-length\");created=1618884473;keyid=\"test-key-rsa-pss\""),
+            ("Signature", "sig-b23=:bbN8oArOxYoyylQQUU6QYwrTuaxLwjAC9fbY2F6SVWvh0yBiMIRGOnMYwZ/5MR6fb0Kh1rIRASVxFkeGt683+qRpRRU5p2voTp768ZrCUb38K0fUxN0O0iC59DzYx8DFll5GmydPxSmme9v6ULbMFkl+V5B1TP/yPViV7KsLNmvKiLJH1pFkh/aYA2HXXZzNBXmIkoQoLd7YfW91kE9o/CCoC1xMy7JA1ipwvKvfrs65ldmlu9bpG6A9BmzhuzF8Eim5f8ui9eH8LZH896+QIF61ka39VBrohr9iyMUJpvRX2Zbhl5ZJzSRxpJyoEZAFL2FUo5fTIztsDZKEgM4cUA==:")
+        ]);
+
+        let mut signed = signed_request(&message).unwrap();
+
+        assert_eq!(signed.signature_base, [
+            "\"date\": Tue, 20 Apr 2021 02:07:55 GMT",
+            "\"@method\": POST",
+            "\"@path\": /foo",
+            "\"@query\": ?param=Value&Pet=dog",
+            "\"@authority\": example.com",
+            "\"content-type\": application/json",
+            &format!("\"content-digest\": {}", CONTENT_DIGEST),
+            "\"content-length\": 18",
+            "\"@signature-params\": (\"date\" \"@method\" \"@path\" \"@query\" \"@authority\" \"content-type\" \"content-digest\" \"content-length\");created=1618884473;keyid=\"test-key-rsa-pss\""
+        ].join("\n"));
+
+        assert_eq!(signed.key_id, "test-key-rsa-pss");
+        assert_eq!(signed.created, Some(1618884473));
+
+        signed.algorithm = Some(Algorithm::RsaPssSha512);
+
+        assert!(verify(&public_key(RSA_PSS_KEY), &signed).unwrap());
+
+        signed.signature[0] ^= 1;
+
+        assert!(!verify(&public_key(RSA_PSS_KEY), &signed).unwrap());
+    }
+
+    // B.2.4, which signs the response to the request
+    #[test]
+    fn ecdsa_p256_sha256() {
+        let message = TestMessage {
+            method: "POST",
+            target: "/foo?param=Value&Pet=dog",
+            status: Some(200),
+            headers: vec![
+                ("Date", "Tue, 20 Apr 2021 02:07:56 GMT".to_owned()),
+                ("Content-Type", "application/json".to_owned()),
+                ("Content-Digest", "sha-512=:mEWXIS7MaLRuGgxOBdODa3xqM1XdEvxoYhvlCFJ41QJgJc4GTsPp29l5oGX69wWdXymyU0rjJuahq4l5aGgfLQ==:".to_owned()),
+                ("Content-Length", "23".to_owned()),
+                ("Signature-Input", "sig-b24=(\"@status\" \"content-type\" \"content-digest\" \"content-length\");created=1618884473;keyid=\"test-key-ecc-p256\"".to_owned()),
+                ("Signature", "sig-b24=:wNmSUAhwb5LxtOtOpNa6W5xj067m5hFrj0XQ4fvpaCLx0NKocgPquLgyahnzDnDAUy5eCdlYUEkLIj+32oiasw==:".to_owned())
+            ]
+        };
+
+        let mut signed = signed_request(&message).unwrap();
+
+        assert_eq!(signed.signature_base, [
+            "\"@status\": 200",
+            "\"content-type\": application/json",
+            "\"content-digest\": sha-512=:mEWXIS7MaLRuGgxOBdODa3xqM1XdEvxoYhvlCFJ41QJgJc4GTsPp29l5oGX69wWdXymyU0rjJuahq4l5aGgfLQ==:",
+            "\"content-length\": 23",
+            "\"@signature-params\": (\"@status\" \"content-type\" \"content-digest\" \"content-length\");created=1618884473;keyid=\"test-key-ecc-p256\""
+        ].join("\n"));
+
+        signed.algorithm = Some(Algorithm::EcdsaP256Sha256);
+
+        assert!(verify(&public_key(ECC_P256_KEY), &signed).unwrap());
+        assert!(verify(&public_key(RSA_PSS_KEY), &signed).is_err());
+
+        signed.signature[63] ^= 1;
+
+        assert!(!verify(&public_key(ECC_P256_KEY), &signed).unwrap());
+
+        // a request has no status to cover
+        assert!(signed_request(&TestMessage { status: None, ..message }).is_err());
+    }
+
+    // The appendix has no rsa-v1_5-sha256 example, so this was signed with the private
+    // half of test-key-rsa; PKCS #1 v1.5 signatures are deterministic
+    #[test]
+    fn rsa_v1_5_sha256() {
+        let message = example_request(vec![
+            ("Signature-Input", "sig1=(\"@method\" \"@authority\" \"@path\" \"content-digest\");created=1618884473;keyid=\"test-key-rsa\";alg=\"rsa-v1_5-sha256\""),
+            ("Signature", "sig1=:UKjiKNX4hAcu0sZbc8q0MnG+VQ3OLH9kzP0uxYt8P/jkzU1dQq14KLmPi9eZeOk65JkoUMmbFSgSaS4Ge9XTMOdmxk1f47LPtwBKRjmPCUysTisZWGt7ONCM1oEfV2SOTRMJMRf9ByCORl5li3772nX5vR9xzThRe2MjsEaS8ZvtdbRYq7cYSsBzVGsaZAWCYjXlVc7xchmJsrpHTE2K2eqZMQQUhxSJdJP2fA28ukJCB3HxajxHny0VHyWOaP3Z7dcXU3Oad+c2cTyY+ESSxgC/J1rks3zh3+Lia8t659eCfhWn3luX8uwd7GMo9zZW5kcgWgNtBrmefA0e82o4Cw==:")
+        ]);
+
+        let signed = signed_request(&message).unwrap();
+
+        assert_eq!(signed.algorithm, Some(Algorithm::RsaSha256));
+        assert!(verify(&public_key(RSA_KEY), &signed).unwrap());
+
+        // the key has to match the algorithm
+        assert!(verify(&public_key(ED25519_KEY), &signed).is_err());
+    }
+
+    // B.2.6
+    #[test]
+    fn ed25519() {
+        let message = example_request(vec![]);
+
+        let signed = rfc9421_signed_request(
+            &message,
+            "sig-b26=(\"date\" \"@method\" \"@path\" \"@authority\" \"content-type\" \"content-length\");created=1618884473;keyid=\"test-key-ed25519\"",
+            "sig-b26=:wqcAqbmYJ2ji2glfAMaRy4gruYYnx2nEFN2HN6jrnDnQCK1u02Gb04v9EDgwUPiu4A0w6vuQv5lIp5WPpBKRCw==:"
+        ).unwrap();
+
+        assert_eq!(signed.signature_base, [
+            "\"date\": Tue, 20 Apr 2021 02:07:55 GMT",
+            "\"@method\": POST",
+            "\"@path\": /foo",
+            "\"@authority\": example.com",
+            "\"content-type\": application/json",
+            "\"content-length\": 18",
+            "\"@signature-params\": (\"date\" \"@method\" \"@path\" \"@authority\" \"content-type\" \"content-length\");created=1618884473;keyid=\"test-key-ed25519\""
+        ].join("\n"));
+
+        assert!(verify(&public_key(ED25519_KEY), &signed).unwrap());
+
+        // nothing covers the body
+        assert_eq!(signed.digest, None);
+    }
+
+    fn valid_signature(digest: Option<SignedDigest>) -> ValidSignature {
+        ValidSignature {
+            key_id: "https://example.com/actor#main-key".to_owned(),
+            owner: "https://example.com/actor".to_owned(),
+            digest: digest
+        }
+    }
+
+    #[test]
+    fn unsigned_content_digest() {
+        let tampered = b"{\"hello\": \"there\"}";
+        let tampered_digest = "sha-256=:syC/vQE9YI+DLlqHuK39zAynpY8NAYk/9zYN6U67Lsk=:";
+
+        assert!(verify_content_digest(tampered_digest, tampered).is_ok());
+
+        // a cavage signature only has to cover digest; the Content-Digest beside it
+        // was added along with a different body
+        let message = TestMessage {
+            method: "POST",
+            target: "/_inbox",
+            status: None,
+            headers: vec![
+                ("Host", "example.com".to_owned()),
+                ("Date", "Tue, 20 Apr 2021 02:07:55 GMT".to_owned()),
+                ("Digest", "SHA-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=".to_owned()),
+                ("Content-Digest", tampered_digest.to_owned()),
+                ("Signature", "keyId=\"https://example.com/actor#main-key\",algorithm=\"rsa-sha256\",headers=\"(request-target) host date digest\",signature=\"AAAA\"".to_owned())
+            ]
+        };
+
+        let signed = signed_request(&message).unwrap();
+
+        assert_eq!(signed.digest, Some(SignedDigest::Digest("SHA-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=".to_owned())));
+
+        let signature = valid_signature(signed.digest);
+
+        assert!(signature.verify_body(BODY).is_ok());
+        assert!(signature.verify_body(tampered).is_err());
+
+        // the same with an RFC 9421 signature that covers digest but not content-digest
+        let message = TestMessage {
+            headers: message.headers.into_iter()
+                .filter(|(name, _)| *name != "Signature")
+                .chain(vec![
+                    ("Signature-Input", "sig1=(\"@method\" \"@path\" \"digest\");created=1618884473;keyid=\"k\"".to_owned()),
+                    ("Signature", "sig1=:AAAA:".to_owned())
+                ])
+                .collect(),
+            ..message
+        };
+
+        let signature = valid_signature(signed_request(&message).unwrap().digest);
+
+        assert!(signature.verify_body(BODY).is_ok());
+        assert!(signature.verify_body(tampered).is_err());
+
+        // with no signed digest at all, the body can't be checked
+        assert!(valid_signature(None).verify_body(BODY).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use ::base64;
+use ::chrono::offset::Utc;
+use ::openssl::{
+    hash::{self, MessageDigest},
+    pkey::{PKey, Private},
+    rsa::Rsa,
+    sign::Signer
+};
+use ::reqwest::header::Headers;
+use ::url::Url;
+
+use config::Config;
+
+// Signs outgoing requests the same way sig.rs expects to verify incoming ones:
+//
+// Signature: keyId="<actor>#main-key",algorithm="rsa-sha256",headers="(request-target) host date digest",signature="..."
+
+pub fn digest(body: &[u8]) -> Result<String, ::failure::Error> {
+    let sha256 = hash::hash(MessageDigest::sha256(), body)?;
+
+    Ok(format!("SHA-256={}", base64::encode(&sha256)))
+}
+
+pub fn content_digest(body: &[u8]) -> Result<String, ::failure::Error> {
+    let sha256 = hash::hash(MessageDigest::sha256(), body)?;
+
+    Ok(format!("sha-256=:{}:", base64::encode(&sha256)))
+}
+
+fn http_date() -> String {
+    Utc::now()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+pub fn host(url: &Url) -> Result<String, ::failure::Error> {
+    let host = url.host_str()
+        .ok_or(format_err!("No host found in {}", url))?;
+
+    Ok(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned()
+    })
+}
+
+fn request_target(method: &str, url: &Url) -> String {
+    let mut target = format!("{} {}", method.to_lowercase(), url.path());
+
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+
+    target
+}
+
+fn private_key(config: &Config) -> Result<PKey<Private>, ::failure::Error> {
+    let rsa = Rsa::private_key_from_pem(config.priv_key.as_bytes())
+        .map_err(|e| format_err!("Failed to get RSA from PEM: {:?}", e))?;
+
+    let key = PKey::from_rsa(rsa)
+        .map_err(|e| format_err!("Failed to get private key from RSA: {:?}", e))?;
+
+    Ok(key)
+}
+
+fn sign(config: &Config, data: &str) -> Result<Vec<u8>, ::failure::Error> {
+    let key = private_key(config)?;
+
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)
+        .map_err(|e| format_err!("Failed to create signer: {:?}", e))?;
+
+    signer.update(data.as_bytes())?;
+
+    Ok(signer.sign_to_vec()?)
+}
+
+pub fn sign_request(config: &Config, method: &str, url: &Url, body: Option<&[u8]>) -> Result<Headers, ::failure::Error> {
+    let mut fields: Vec<(&'static str, String)> = vec![
+        ("(request-target)", request_target(method, url)),
+        ("host", host(url)?),
+        ("date", http_date())
+    ];
+
+    if let Some(body) = body {
+        fields.push(("digest", digest(body)?));
+    }
+
+    let comparison_string: String = fields.iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let signature = sign(config, &comparison_string)?;
+
+    let header_names: String = fields.iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    let signature_header = format!(
+        "keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"{}\",signature=\"{}\"",
+        config.key_id(),
+        header_names,
+        base64::encode(&signature)
+    );
+
+    let mut headers = Headers::new();
+
+    for (name, value) in fields {
+        if name != "(request-target)" {
+            headers.set_raw(name, value);
+        }
+    }
+
+    headers.set_raw("Signature", signature_header);
+
+    Ok(headers)
+}
+
+// RFC 9421 HTTP Message Signatures, for peers that advertise support:
+//
+// Signature-Input: sig1=("@method" "@target-uri" "content-digest");created=1618884473;keyid="<actor>#main-key";alg="rsa-v1_5-sha256"
+// Signature: sig1=:...:
+
+pub fn sign_request_rfc9421(config: &Config, method: &str, url: &Url, body: Option<&[u8]>) -> Result<Headers, ::failure::Error> {
+    let mut components: Vec<(&'static str, String)> = vec![
+        ("@method", method.to_uppercase()),
+        ("@target-uri", url.as_str().to_owned())
+    ];
+
+    if let Some(body) = body {
+        components.push(("content-digest", content_digest(body)?));
+    }
+
+    let component_names: String = components.iter()
+        .map(|(name, _)| format!("\"{}\"", name))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    let signature_params = format!(
+        "({});created={};keyid=\"{}\";alg=\"rsa-v1_5-sha256\"",
+        component_names,
+        Utc::now().timestamp(),
+        config.key_id()
+    );
+
+    let mut lines: Vec<String> = components.iter()
+        .map(|(name, value)| format!("\"{}\": {}", name, value))
+        .collect();
+
+    lines.push(format!("\"@signature-params\": {}", signature_params));
+
+    let signature = sign(config, &lines.join("\n"))?;
+
+    let mut headers = Headers::new();
+
+    headers.set_raw("host", host(url)?);
+    headers.set_raw("date", http_date());
+
+    for (name, value) in components {
+        if !name.starts_with('@') {
+            headers.set_raw(name, value);
+        }
+    }
+
+    headers.set_raw("Signature-Input", format!("sig1={}", signature_params));
+    headers.set_raw("Signature", format!("sig1=:{}:", base64::encode(&signature)));
+
+    Ok(headers)
+}
@@ -0,0 +1,123 @@
+use ::std::fs::{self, File};
+use ::std::io::{self, Read, Seek, SeekFrom};
+use ::std::path::{Path, PathBuf};
+use ::std::time::UNIX_EPOCH;
+
+use ::chrono::{DateTime, TimeZone, Utc};
+
+use config::{Config, StoreConfig};
+use s3::S3Store;
+
+// Where media files live. Keys are the file names pieces and /_media urls use, e.g.
+// "<sha256 hex>.mp3". Uploads are written to a temporary file in media_dir first,
+// which `put` then moves into the store.
+
+#[derive(Debug, Clone)]
+pub struct Stat {
+    pub length: u64,
+    pub modified: DateTime<Utc>
+}
+
+pub type Reader = Box<Read + Send>;
+
+pub trait MediaStore: Send + Sync {
+    // moves source into the store, so it's gone afterwards
+    fn put(&self, key: &str, source: &Path, media_type: &str) -> Result<(), ::failure::Error>;
+
+    fn get(&self, key: &str) -> Result<Reader, ::failure::Error>;
+
+    fn get_range(&self, key: &str, start: u64, length: u64) -> Result<Reader, ::failure::Error>;
+
+    // None if there's no such file
+    fn stat(&self, key: &str) -> Result<Option<Stat>, ::failure::Error>;
+
+    fn delete(&self, key: &str) -> Result<(), ::failure::Error>;
+
+    // a url to send clients to instead of proxying the file, if the store has one
+    fn redirect_url(&self, key: &str) -> Result<Option<String>, ::failure::Error>;
+
+    // whether keys can have directories in them, like "2018/mix.mp3"
+    fn nested_keys(&self) -> bool;
+}
+
+// Keeps media in media_dir, as jadwiga always has
+#[derive(Debug)]
+pub struct FsStore {
+    root: PathBuf
+}
+
+impl FsStore {
+    pub fn new(root: &str) -> Self {
+        FsStore {
+            root: PathBuf::from(root)
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl MediaStore for FsStore {
+    fn put(&self, key: &str, source: &Path, _media_type: &str) -> Result<(), ::failure::Error> {
+        fs::rename(source, self.path(key))?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Reader, ::failure::Error> {
+        Ok(Box::new(File::open(self.path(key))?))
+    }
+
+    fn get_range(&self, key: &str, start: u64, length: u64) -> Result<Reader, ::failure::Error> {
+        let mut file = File::open(self.path(key))?;
+        file.seek(SeekFrom::Start(start))?;
+
+        Ok(Box::new(file.take(length)))
+    }
+
+    fn stat(&self, key: &str) -> Result<Option<Stat>, ::failure::Error> {
+        let metadata = match fs::metadata(self.path(key)) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into())
+        };
+
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+
+        let modified_secs = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Ok(Some(Stat {
+            length: metadata.len(),
+            modified: Utc.timestamp(modified_secs as i64, 0)
+        }))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), ::failure::Error> {
+        match fs::remove_file(self.path(key)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => Ok(result?)
+        }
+    }
+
+    fn redirect_url(&self, _key: &str) -> Result<Option<String>, ::failure::Error> {
+        Ok(None)
+    }
+
+    // anything put in media_dir by hand has always been served, subdirectories included
+    fn nested_keys(&self) -> bool {
+        true
+    }
+}
+
+pub fn open(config: &Config) -> Result<Box<MediaStore>, ::failure::Error> {
+    Ok(match config.store {
+        StoreConfig::Fs => Box::new(FsStore::new(&config.media_dir)),
+        StoreConfig::S3(ref s3_config) => Box::new(S3Store::new(s3_config.clone())?)
+    })
+}
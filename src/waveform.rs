@@ -0,0 +1,243 @@
+use ::std::cmp;
+use ::std::fs::File;
+use ::std::io::BufReader;
+use ::std::path::Path;
+
+use ::claxon::FlacReader;
+use ::lewton::inside_ogg::OggStreamReader;
+use ::png::{self, HasParameters};
+use ::puremp3;
+
+// Waveforms are drawn from min/max peaks of the decoded audio, kept in the media
+// table as JSON laid out like audiowaveform's, with a couple of resolutions:
+//
+// {
+//     "version": 2, "channels": 1, "sample_rate": 44100, "bits": 8,
+//     "resolutions": [
+//         {"samples_per_pixel": 1024, "length": 1500, "data": [min, max, min, max, ...]},
+//         ...
+//     ]
+// }
+//
+// MP3, FLAC and Vorbis are decoded; there's no pure Rust Opus decoder, so Opus
+// tracks go without.
+
+// every channel is folded into the one envelope
+const SAMPLES_PER_PEAK: usize = 256;
+// the most peaks kept at each resolution, enough for a full-width player and a thumbnail
+const RESOLUTIONS: [usize; 2] = [1600, 200];
+
+const IMAGE_WIDTH: u32 = 1200;
+const IMAGE_HEIGHT: u32 = 240;
+const BACKGROUND: [u8; 3] = [0xFA, 0xFA, 0xFA];
+const FOREGROUND: [u8; 3] = [0x33, 0x33, 0x33];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resolution {
+    pub samples_per_pixel: usize,
+    pub length: usize,
+    pub data: Vec<i8>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peaks {
+    pub version: u32,
+    pub channels: u32,
+    pub sample_rate: u32,
+    pub bits: u32,
+    pub resolutions: Vec<Resolution>
+}
+
+struct PeakBuilder {
+    samples_per_peak: usize,
+    count: usize,
+    min: f32,
+    max: f32,
+    peaks: Vec<(f32, f32)>
+}
+
+impl PeakBuilder {
+    fn new(channels: usize) -> Self {
+        PeakBuilder {
+            samples_per_peak: SAMPLES_PER_PEAK * cmp::max(channels, 1),
+            count: 0,
+            min: 0.0,
+            max: 0.0,
+            peaks: Vec::new()
+        }
+    }
+
+    // samples run from -1.0 to 1.0
+    fn push(&mut self, sample: f32) {
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.count += 1;
+
+        if self.count == self.samples_per_peak {
+            self.peaks.push((self.min, self.max));
+            self.count = 0;
+            self.min = 0.0;
+            self.max = 0.0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<(f32, f32)> {
+        if self.count > 0 {
+            self.peaks.push((self.min, self.max));
+        }
+
+        self.peaks
+    }
+}
+
+fn to_i8(sample: f32) -> i8 {
+    (sample.max(-1.0).min(1.0) * 127.0).round() as i8
+}
+
+// merges neighbouring peaks until there are at most `length` of them
+fn downsample(peaks: &[(f32, f32)], length: usize) -> Resolution {
+    let group = cmp::max((peaks.len() + length - 1) / length, 1);
+
+    let data: Vec<i8> = peaks.chunks(group)
+        .flat_map(|chunk| {
+            let min = chunk.iter().fold(0.0f32, |min, &(peak, _)| min.min(peak));
+            let max = chunk.iter().fold(0.0f32, |max, &(_, peak)| max.max(peak));
+
+            vec![to_i8(min), to_i8(max)]
+        })
+        .collect();
+
+    Resolution {
+        samples_per_pixel: SAMPLES_PER_PEAK * group,
+        length: data.len() / 2,
+        data: data
+    }
+}
+
+fn decode_mp3(path: &Path) -> Result<Vec<(f32, f32)>, ::failure::Error> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let (_, samples) = puremp3::read_mp3(reader)
+        .map_err(|e| format_err!("Failed to decode MP3: {:?}", e))?;
+
+    // always decoded as a left and right pair
+    let mut builder = PeakBuilder::new(2);
+
+    for (left, right) in samples {
+        builder.push(left);
+        builder.push(right);
+    }
+
+    Ok(builder.finish())
+}
+
+fn decode_flac(path: &Path) -> Result<Vec<(f32, f32)>, ::failure::Error> {
+    let mut reader = FlacReader::new(BufReader::new(File::open(path)?))
+        .map_err(|e| format_err!("Failed to decode FLAC: {:?}", e))?;
+
+    let channels = reader.streaminfo().channels as usize;
+    let scale = (1u64 << (reader.streaminfo().bits_per_sample - 1)) as f32;
+
+    let mut builder = PeakBuilder::new(channels);
+
+    for sample in reader.samples() {
+        let sample = sample.map_err(|e| format_err!("Failed to decode FLAC: {:?}", e))?;
+        builder.push(sample as f32 / scale);
+    }
+
+    Ok(builder.finish())
+}
+
+fn decode_vorbis(path: &Path) -> Result<Vec<(f32, f32)>, ::failure::Error> {
+    let mut reader = OggStreamReader::new(BufReader::new(File::open(path)?))
+        .map_err(|e| format_err!("Failed to decode Vorbis: {:?}", e))?;
+
+    let mut builder = PeakBuilder::new(reader.ident_hdr.audio_channels as usize);
+
+    while let Some(packet) = reader.read_dec_packet_itl().map_err(|e| format_err!("Failed to decode Vorbis: {:?}", e))? {
+        for sample in packet {
+            builder.push(sample as f32 / 32768.0);
+        }
+    }
+
+    Ok(builder.finish())
+}
+
+// None for audio that can't be decoded
+pub fn compute(path: &Path, media_type: &str, sample_rate: Option<i32>) -> Result<Option<Peaks>, ::failure::Error> {
+    let peaks = match media_type {
+        "audio/mpeg" | "audio/mp3" => decode_mp3(path)?,
+        "audio/flac" | "audio/x-flac" => decode_flac(path)?,
+        // Ogg files may hold Opus rather than Vorbis, which lewton refuses
+        "audio/ogg" | "audio/vorbis" => decode_vorbis(path)?,
+        _ => return Ok(None)
+    };
+
+    if peaks.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Peaks {
+        version: 2,
+        channels: 1,
+        sample_rate: sample_rate.unwrap_or(0) as u32,
+        bits: 8,
+        resolutions: RESOLUTIONS.iter()
+            .map(|&length| downsample(&peaks, length))
+            .collect()
+    }))
+}
+
+// Draws the most detailed resolution across the image as mirrored bars around the
+// centre line. Without peaks the line is flat, so every track still has an image.
+pub fn render_png(peaks: Option<&Peaks>) -> Result<Vec<u8>, ::failure::Error> {
+    let width = IMAGE_WIDTH as usize;
+    let height = IMAGE_HEIGHT as usize;
+    let centre = height / 2;
+
+    let mut pixels = BACKGROUND.iter()
+        .cloned()
+        .cycle()
+        .take(width * height * 3)
+        .collect::<Vec<u8>>();
+
+    let data = peaks
+        .and_then(|peaks| peaks.resolutions.first())
+        .map(|resolution| resolution.data.as_slice())
+        .unwrap_or(&[]);
+
+    let length = data.len() / 2;
+
+    for x in 0..width {
+        let (min, max) = if length == 0 {
+            (0, 0)
+        } else {
+            let start = x * length / width;
+            let end = cmp::max((x + 1) * length / width, start + 1);
+
+            data[start * 2..end * 2].chunks(2)
+                .fold((0i32, 0i32), |(min, max), peak| (min.min(peak[0] as i32), max.max(peak[1] as i32)))
+        };
+
+        // at least a pixel either side of the centre line
+        let top = centre - cmp::max(max as usize * centre / 128, 1);
+        let bottom = centre + cmp::max((-min) as usize * centre / 128, 1);
+
+        for y in top..bottom {
+            let offset = (y * width + x) * 3;
+            pixels[offset..offset + 3].copy_from_slice(&FOREGROUND);
+        }
+    }
+
+    let mut image = Vec::new();
+
+    {
+        let mut encoder = png::Encoder::new(&mut image, IMAGE_WIDTH, IMAGE_HEIGHT);
+        encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+    }
+
+    Ok(image)
+}